@@ -0,0 +1,94 @@
+use crate::hash::HashAlgorithm;
+#[cfg(feature = "serde")]
+use crate::hex;
+use crate::hex::HexError;
+use crate::util::{bytes_to_hex, hex_to_bytes};
+use crate::HASH_SIZE;
+use ed25519_dalek::PublicKey;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+// A user's address: the hash of their public key, kept distinct from
+// block and transaction hashes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Address(pub [u8; HASH_SIZE]);
+
+// Hashes exactly like the bytes it borrows as, so balances and key
+// registries can be searched with a plain byte slice
+impl Hash for Address {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0[..].hash(state);
+    }
+}
+
+impl Borrow<[u8]> for Address {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Address {
+    // The same key always gives the same address
+    pub fn from_public_key(pk: &PublicKey) -> Address {
+        Self::from_public_key_with(pk, &HashAlgorithm::default())
+    }
+
+    pub fn from_public_key_with(
+        pk: &PublicKey,
+        algorithm: &HashAlgorithm,
+    ) -> Address {
+        Address(algorithm.digest(pk.as_bytes()).0)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", bytes_to_hex(&self.0))
+    }
+}
+
+impl FromStr for Address {
+    type Err = HexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex_to_bytes(s)?;
+        let len = bytes.len();
+        bytes.try_into().map(Address).map_err(|_| {
+            HexError::InvalidLength {
+                expected: HASH_SIZE,
+                got: len,
+            }
+        })
+    }
+}
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Hex in JSON, raw bytes in bincode
+#[cfg(feature = "serde")]
+impl Serialize for Address {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        hex::serialize(&self.0, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        hex::deserialize(deserializer).map(Address)
+    }
+}
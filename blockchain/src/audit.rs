@@ -0,0 +1,256 @@
+use crate::error::AuditError;
+use crate::{Address, Blockchain, Hash, HashAlgorithm, Txn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+
+// The audit log is JSON lines, one entry per line:
+//
+//   {"seq":0,"event":{"type":"block_added",...},"prev":"<hex>","hash":"<hex>"}
+//
+// `seq` counts up from 0 with no gaps. `prev` is the previous entry's
+// `hash`, or all zeroes for the first entry. `hash` is the default
+// algorithm's digest of the 32 bytes of `prev`, then `seq` as a
+// little-endian u64, then the compact JSON of `event` as written, which has
+// its keys in sorted order.
+// Changing an entry, or dropping or reordering entries, breaks the chain
+// from there on.
+//
+// Events, told apart by their "type":
+//   block_added         index, hash, transactions
+//   transaction_applied block_index, txn_id, sender, recipient, amount, fee
+//   balance_changed     address, before, after
+//
+// New fields and event types may be added, so readers should ignore what
+// they do not know.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditEvent {
+    BlockAdded {
+        index: u32,
+        hash: Hash,
+        transactions: usize,
+    },
+    TransactionApplied {
+        block_index: u32,
+        txn_id: Hash,
+        sender: Address,
+        recipient: Address,
+        amount: u64,
+        fee: u64,
+    },
+    BalanceChanged {
+        address: Address,
+        before: u64,
+        after: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditEntry {
+    seq: u64,
+    event: serde_json::Value,
+    prev: Hash,
+    hash: Hash,
+}
+
+fn entry_hash(prev: &Hash, seq: u64, event: &[u8]) -> Hash {
+    let mut bytes = prev.0.to_vec();
+    bytes.extend_from_slice(&seq.to_le_bytes());
+    bytes.extend_from_slice(event);
+    HashAlgorithm::default().digest(&bytes)
+}
+
+// The sequence number and running hash of the entries written so far
+#[derive(Default)]
+pub struct AuditLog {
+    seq: u64,
+    prev: Hash,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog {
+            seq: 0,
+            prev: Hash::default(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        mut writer: impl Write,
+        event: &AuditEvent,
+    ) -> Result<(), AuditError> {
+        let event = serde_json::to_value(event)?;
+        let hash =
+            entry_hash(&self.prev, self.seq, event.to_string().as_bytes());
+        let entry = AuditEntry {
+            seq: self.seq,
+            event,
+            prev: self.prev,
+            hash,
+        };
+        serde_json::to_writer(&mut writer, &entry)?;
+        writer.write_all(b"\n")?;
+        self.seq += 1;
+        self.prev = hash;
+        Ok(())
+    }
+
+    // Check every entry's sequence number and hash against the ones before
+    // it, and return how many entries there were
+    pub fn verify_log(reader: impl Read) -> Result<u64, AuditError> {
+        let mut prev = Hash::default();
+        let mut seq = 0;
+        for line in BufReader::new(reader).lines() {
+            let entry: AuditEntry = serde_json::from_str(&line?)?;
+            if entry.seq != seq {
+                return Err(AuditError::OutOfSequence {
+                    expected: seq,
+                    found: entry.seq,
+                });
+            }
+            let hash =
+                entry_hash(&prev, seq, entry.event.to_string().as_bytes());
+            if entry.prev != prev || entry.hash != hash {
+                return Err(AuditError::BrokenChain { seq });
+            }
+            prev = hash;
+            seq += 1;
+        }
+        Ok(seq)
+    }
+}
+
+impl Blockchain {
+    // Replay the chain, logging each block, each transaction in it and the
+    // balances that transaction moved
+    pub fn audit_log(
+        &self,
+        mut writer: impl Write,
+    ) -> Result<(), AuditError> {
+        let mut log = AuditLog::new();
+        let (mut balances, start) = if self.is_pruned() {
+            (self.pruned_state.balances.clone(), self.offset())
+        } else {
+            (HashMap::new(), 0)
+        };
+        for block in &self.blocks[start..] {
+            log.record(
+                &mut writer,
+                &AuditEvent::BlockAdded {
+                    index: block.index,
+                    hash: block.hash,
+                    transactions: block.txns.len(),
+                },
+            )?;
+            for txn in block.txns.iter() {
+                log.record(
+                    &mut writer,
+                    &AuditEvent::TransactionApplied {
+                        block_index: block.index,
+                        txn_id: txn.id,
+                        sender: txn.sender,
+                        recipient: txn.recipient,
+                        amount: txn.amount,
+                        fee: txn.fee,
+                    },
+                )?;
+                let before = touched_balances(&balances, txn);
+                Blockchain::apply_to_balances(&mut balances, txn)?;
+                for (address, before) in before {
+                    let after =
+                        balances.get(&address).copied().unwrap_or(0);
+                    if after != before {
+                        log.record(
+                            &mut writer,
+                            &AuditEvent::BalanceChanged {
+                                address,
+                                before,
+                                after,
+                            },
+                        )?;
+                    }
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// Sender then recipient, as they stand before the transaction. Coinbase
+// rewards come from nowhere, so only their recipient is touched.
+fn touched_balances(
+    balances: &HashMap<Address, u64>,
+    txn: &Txn,
+) -> Vec<(Address, u64)> {
+    let mut addresses = vec![txn.recipient];
+    if !txn.is_coinbase() {
+        addresses.insert(0, txn.sender);
+    }
+    addresses
+        .into_iter()
+        .map(|address| {
+            (address, balances.get(&address).copied().unwrap_or(0))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{keyed_user, transfer_chain, walked_chain};
+    use crate::validation::ValidationError;
+
+    // The log needs every transfer to be funded
+    #[test]
+    fn unfunded_chains_cannot_be_logged() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        assert!(matches!(
+            chain.audit_log(std::io::sink()),
+            Err(AuditError::Invalid(
+                ValidationError::InsufficientFunds { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn logs_are_hash_linked_lists_of_events() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut log = Vec::new();
+        walked.audit_log(&mut log).unwrap();
+        let entries = AuditLog::verify_log(&log[..]).unwrap();
+        assert_eq!(
+            entries as usize,
+            String::from_utf8_lossy(&log).lines().count()
+        );
+        assert!(
+            entries as usize
+                > walked.blocks.len() + walked.total_transaction_count()
+        );
+        let log = String::from_utf8(log).unwrap();
+        let first = log.lines().next().unwrap();
+        assert!(first.starts_with("{\"seq\":0,\"event\":{"));
+        assert!(first.contains("\"type\":\"block_added\""));
+        assert!(log.contains("\"type\":\"balance_changed\""));
+
+        let tampered = log.replacen("\"amount\":", "\"amount\":1", 1);
+        assert!(matches!(
+            AuditLog::verify_log(tampered.as_bytes()),
+            Err(AuditError::BrokenChain { .. })
+        ));
+        let (_, truncated) = log.split_once('\n').unwrap();
+        assert!(matches!(
+            AuditLog::verify_log(truncated.as_bytes()),
+            Err(AuditError::OutOfSequence {
+                expected: 0,
+                found: 1
+            })
+        ));
+    }
+}
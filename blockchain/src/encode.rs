@@ -0,0 +1,161 @@
+use crate::{Address, Block, Hash, Txn, TxnKind, Txns, User};
+use ed25519_dalek::PublicKey;
+
+// The bytes bincode writes for a value: fixed-width little-endian integers,
+// u64 lengths before sequences, a u8 tag before options and a u32 variant
+// index before enums. Hashes, signatures and block sizes are computed from
+// this, so they do not change with the serde feature.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn to_encoded(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u128 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+// Fixed-size arrays have no length
+impl Encode for Hash {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl Encode for Address {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+// Written as a byte string, so it does have a length
+impl Encode for PublicKey {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_bytes()[..].encode(out);
+    }
+}
+
+impl Encode for TxnKind {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            TxnKind::Transfer => 0u32.encode(out),
+            TxnKind::CoinbaseReward { block_index } => {
+                1u32.encode(out);
+                block_index.encode(out);
+            }
+            TxnKind::MultiSig {
+                required,
+                signatories,
+            } => {
+                2u32.encode(out);
+                required.encode(out);
+                signatories[..].encode(out);
+            }
+        }
+    }
+}
+
+impl Encode for Txn {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.version.encode(out);
+        self.id.encode(out);
+        self.kind.encode(out);
+        self.sender.encode(out);
+        self.recipient.encode(out);
+        self.amount.encode(out);
+        self.fee.encode(out);
+        self.timestamp.encode(out);
+        self.expiry.encode(out);
+        self.metadata.as_deref().encode(out);
+        self.signature[..].encode(out);
+    }
+}
+
+impl Encode for Txns {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        for txn in self.iter() {
+            txn.encode(out);
+        }
+        self.merkle_root.encode(out);
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.hash.encode(out);
+        self.prev_hash.encode(out);
+        self.txns.encode(out);
+        self.index.encode(out);
+        self.timestamp.encode(out);
+        self.nonce.encode(out);
+    }
+}
+
+impl Encode for User {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.address.encode(out);
+        self.timestamp.encode(out);
+        self.nonce.encode(out);
+        self.public_key.encode(out);
+        self.uid.encode(out);
+    }
+}
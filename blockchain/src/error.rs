@@ -0,0 +1,729 @@
+use crate::hex::HexError;
+use crate::validation::ValidationError;
+use crate::Hash;
+use ed25519_dalek::SignatureError;
+use std::fmt;
+use std::io;
+
+// Why a blockchain, user or key operation failed. More variants may be
+// added, so matches outside this crate need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BlockchainError {
+    Io(io::Error),
+    #[cfg(feature = "serde")]
+    Serialization(bincode::Error),
+    Crypto(SignatureError),
+    Rng(rand::Error),
+    Invalid(ValidationError),
+    InvalidHash,
+    InvalidSignature,
+    KeyNotFound(String), // The uid whose key is missing
+    InvalidKeyFile {
+        uid: String,
+        error: KeyError,
+    },
+    PruneOutOfRange {
+        keep_from: u32,
+        height: u32,
+    },
+    // Would remove genesis, or on a pruned chain the first kept block
+    CannotRollbackGenesis,
+    BlockTooLarge {
+        size: usize,
+        max: usize,
+    }, // Serialized bytes
+    // A block in a batch failed after `applied` blocks were added
+    BatchFailed {
+        applied: u32,
+        error: Box<BlockchainError>,
+    },
+    Transaction(TransactionError), // No more specific variant applies
+    User(UserError),               // No more specific variant applies
+    Storage(StorageError),
+    #[cfg(feature = "serde")]
+    Migration(MigrationError), // No more specific variant applies
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockchainError::Io(e) => write!(f, "i/o error: {}", e),
+            #[cfg(feature = "serde")]
+            BlockchainError::Serialization(e) => {
+                write!(f, "serialization error: {}", e)
+            }
+            BlockchainError::Crypto(e) => write!(f, "crypto error: {}", e),
+            BlockchainError::Rng(e) => {
+                write!(f, "random number generator error: {}", e)
+            }
+            BlockchainError::Invalid(e) => {
+                write!(f, "invalid blockchain: {}", e)
+            }
+            BlockchainError::InvalidHash => write!(f, "invalid hash"),
+            BlockchainError::InvalidSignature => {
+                write!(f, "invalid signature")
+            }
+            BlockchainError::KeyNotFound(uid) => {
+                write!(f, "no key for user {}", uid)
+            }
+            BlockchainError::InvalidKeyFile { uid, error } => {
+                write!(
+                    f,
+                    "key file of user {} is unreadable: {}",
+                    uid, error
+                )
+            }
+            BlockchainError::PruneOutOfRange { keep_from, height } => {
+                write!(
+                    f,
+                    "cannot prune up to block {} of a chain of height {}",
+                    keep_from, height
+                )
+            }
+            BlockchainError::CannotRollbackGenesis => {
+                write!(f, "cannot roll back past the first block")
+            }
+            BlockchainError::BlockTooLarge { size, max } => write!(
+                f,
+                "block is {} bytes, at most {} allowed",
+                size, max
+            ),
+            BlockchainError::BatchFailed { applied, error } => write!(
+                f,
+                "{} (after adding {} blocks of the batch)",
+                error, applied
+            ),
+            BlockchainError::Transaction(e) => write!(f, "{}", e),
+            BlockchainError::User(e) => write!(f, "{}", e),
+            BlockchainError::Storage(e) => write!(f, "{}", e),
+            #[cfg(feature = "serde")]
+            BlockchainError::Migration(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlockchainError {}
+
+impl From<io::Error> for BlockchainError {
+    fn from(e: io::Error) -> Self {
+        BlockchainError::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for BlockchainError {
+    fn from(e: bincode::Error) -> Self {
+        BlockchainError::Serialization(e)
+    }
+}
+
+impl From<SignatureError> for BlockchainError {
+    fn from(e: SignatureError) -> Self {
+        BlockchainError::Crypto(e)
+    }
+}
+
+impl From<rand::Error> for BlockchainError {
+    fn from(e: rand::Error) -> Self {
+        BlockchainError::Rng(e)
+    }
+}
+
+impl From<ValidationError> for BlockchainError {
+    fn from(e: ValidationError) -> Self {
+        match e {
+            ValidationError::BlockTooLarge { size, .. } => {
+                BlockchainError::BlockTooLarge {
+                    size,
+                    max: crate::MAX_BLOCK_SIZE,
+                }
+            }
+            e => BlockchainError::Invalid(e),
+        }
+    }
+}
+
+impl From<TransactionError> for BlockchainError {
+    fn from(e: TransactionError) -> Self {
+        match e {
+            #[cfg(feature = "serde")]
+            TransactionError::Serialization(e) => {
+                BlockchainError::Serialization(e)
+            }
+            TransactionError::Crypto(e) => BlockchainError::Crypto(e),
+            TransactionError::Invalid(e) => BlockchainError::Invalid(e),
+            TransactionError::InvalidHash => BlockchainError::InvalidHash,
+            TransactionError::InvalidSignature => {
+                BlockchainError::InvalidSignature
+            }
+            e => BlockchainError::Transaction(e),
+        }
+    }
+}
+
+impl From<UserError> for BlockchainError {
+    fn from(e: UserError) -> Self {
+        match e {
+            UserError::Io(e) => BlockchainError::Io(e),
+            #[cfg(feature = "serde")]
+            UserError::Serialization(e) => {
+                BlockchainError::Serialization(e)
+            }
+            UserError::Rng(e) => BlockchainError::Rng(e),
+            e => BlockchainError::User(e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MigrationError> for BlockchainError {
+    fn from(e: MigrationError) -> Self {
+        match e {
+            MigrationError::Serialization(e) => {
+                BlockchainError::Serialization(e)
+            }
+            MigrationError::InvalidHash => BlockchainError::InvalidHash,
+            MigrationError::Invalid(e) => BlockchainError::Invalid(e),
+            e => BlockchainError::Migration(e),
+        }
+    }
+}
+
+impl From<StorageError> for BlockchainError {
+    fn from(e: StorageError) -> Self {
+        BlockchainError::Storage(e)
+    }
+}
+
+// Why a persisted file could not be read back
+#[derive(Debug, PartialEq, Eq)]
+pub enum StorageError {
+    CorruptedFile, // Truncated, or its MAC does not match
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::CorruptedFile => {
+                write!(f, "file is truncated or corrupted")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+// Why a key file could not be read back
+#[derive(Debug)]
+pub enum KeyError {
+    Io(io::Error),
+    UnsupportedVersion(u8),
+    InvalidLength(usize), // Of the whole file
+    ChecksumMismatch,
+    Malformed(SignatureError), // Checksum matched but not a keypair
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyError::Io(e) => write!(f, "i/o error: {}", e),
+            KeyError::UnsupportedVersion(version) => {
+                write!(f, "unsupported key file version {}", version)
+            }
+            KeyError::InvalidLength(len) => {
+                write!(f, "key file is {} bytes, not a known size", len)
+            }
+            KeyError::ChecksumMismatch => {
+                write!(f, "key file checksum does not match")
+            }
+            KeyError::Malformed(e) => {
+                write!(f, "malformed keypair: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl From<io::Error> for KeyError {
+    fn from(e: io::Error) -> Self {
+        KeyError::Io(e)
+    }
+}
+
+impl From<SignatureError> for KeyError {
+    fn from(e: SignatureError) -> Self {
+        KeyError::Malformed(e)
+    }
+}
+
+// Why a user could not be encoded, decoded or given a new key
+#[derive(Debug)]
+pub enum UserError {
+    Io(io::Error),
+    #[cfg(feature = "serde")]
+    Serialization(bincode::Error),
+    Rng(rand::Error),
+    InvalidAddress, // The address is not the hash of the public key
+    InvalidUid(String),
+    AlreadyExists(String), // The uid has a user file or key on disk
+    InvalidHex(HexError),
+    MalformedPublicKey(SignatureError),
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserError::Io(e) => write!(f, "i/o error: {}", e),
+            #[cfg(feature = "serde")]
+            UserError::Serialization(e) => {
+                write!(f, "serialization error: {}", e)
+            }
+            UserError::Rng(e) => {
+                write!(f, "random number generator error: {}", e)
+            }
+            UserError::InvalidAddress => {
+                write!(f, "user address does not match public key")
+            }
+            UserError::InvalidUid(uid) => {
+                write!(f, "invalid uid {:?}", uid)
+            }
+            UserError::AlreadyExists(uid) => {
+                write!(f, "user {} already exists", uid)
+            }
+            UserError::InvalidHex(e) => write!(f, "invalid hex: {}", e),
+            UserError::MalformedPublicKey(e) => {
+                write!(f, "malformed public key: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UserError {}
+
+impl From<io::Error> for UserError {
+    fn from(e: io::Error) -> Self {
+        UserError::Io(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for UserError {
+    fn from(e: bincode::Error) -> Self {
+        UserError::Serialization(e)
+    }
+}
+
+impl From<rand::Error> for UserError {
+    fn from(e: rand::Error) -> Self {
+        UserError::Rng(e)
+    }
+}
+
+impl From<HexError> for UserError {
+    fn from(e: HexError) -> Self {
+        UserError::InvalidHex(e)
+    }
+}
+
+impl From<SignatureError> for UserError {
+    fn from(e: SignatureError) -> Self {
+        UserError::MalformedPublicKey(e)
+    }
+}
+
+// Why a single transaction could not be decoded or checked. More variants
+// may be added, so matches outside this crate need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TransactionError {
+    #[cfg(feature = "serde")]
+    Serialization(bincode::Error),
+    Crypto(SignatureError),
+    Invalid(ValidationError),
+    InvalidHash,
+    InvalidSignature,
+    Coinbase, // Rewards only enter the chain inside a block
+    AlreadySigned,
+    AlreadyIncluded {
+        block_index: u32,
+    }, // Replay of a mined transaction
+    TooLarge {
+        size: usize,
+    }, // Encoded bytes, over MAX_TXN_SIZE
+    #[cfg(feature = "serde")]
+    Migration(MigrationError), // No more specific variant applies
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "serde")]
+            TransactionError::Serialization(e) => {
+                write!(f, "serialization error: {}", e)
+            }
+            TransactionError::Crypto(e) => {
+                write!(f, "crypto error: {}", e)
+            }
+            TransactionError::Invalid(e) => {
+                write!(f, "invalid transaction: {}", e)
+            }
+            TransactionError::InvalidHash => {
+                write!(f, "transaction id does not match contents")
+            }
+            TransactionError::InvalidSignature => {
+                write!(f, "invalid transaction signature")
+            }
+            TransactionError::AlreadySigned => {
+                write!(f, "transaction already carries this signature")
+            }
+            TransactionError::AlreadyIncluded { block_index } => {
+                write!(
+                    f,
+                    "transaction is already in block {}",
+                    block_index
+                )
+            }
+            TransactionError::TooLarge { size } => write!(
+                f,
+                "transaction is {} bytes, at most {} allowed",
+                size,
+                crate::MAX_TXN_SIZE
+            ),
+            TransactionError::Coinbase => {
+                write!(
+                    f,
+                    "coinbase rewards cannot be applied on their own"
+                )
+            }
+            #[cfg(feature = "serde")]
+            TransactionError::Migration(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for TransactionError {
+    fn from(e: bincode::Error) -> Self {
+        TransactionError::Serialization(e)
+    }
+}
+
+impl From<ValidationError> for TransactionError {
+    fn from(e: ValidationError) -> Self {
+        TransactionError::Invalid(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MigrationError> for TransactionError {
+    fn from(e: MigrationError) -> Self {
+        match e {
+            MigrationError::Serialization(e) => {
+                TransactionError::Serialization(e)
+            }
+            MigrationError::InvalidHash => TransactionError::InvalidHash,
+            MigrationError::Invalid(e) => TransactionError::Invalid(e),
+            e => TransactionError::Migration(e),
+        }
+    }
+}
+
+// Why a transaction in an older layout could not be brought up to date
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum MigrationError {
+    Serialization(bincode::Error),
+    UnsupportedVersion(u8),
+    UnsupportedSchema(u32),   // Of a chain file
+    InvalidHash, // The id does not match the old layout either
+    Invalid(ValidationError), // A chain whose blocks cannot be rehashed
+    InvalidBlock(u32), // Does not verify under its old schema, by index
+    MiningLimit(u32), // Could not be mined again, by index
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Serialization(e) => {
+                write!(f, "serialization error: {}", e)
+            }
+            MigrationError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "no migration from transaction version {}",
+                    version
+                )
+            }
+            MigrationError::UnsupportedSchema(version) => {
+                write!(
+                    f,
+                    "no migration from chain schema version {}",
+                    version
+                )
+            }
+            MigrationError::InvalidHash => {
+                write!(f, "transaction id does not match contents")
+            }
+            MigrationError::Invalid(e) => write!(f, "{}", e),
+            MigrationError::InvalidBlock(index) => {
+                write!(
+                    f,
+                    "block {} does not verify under its old schema",
+                    index
+                )
+            }
+            MigrationError::MiningLimit(index) => {
+                write!(f, "block {} could not be mined again", index)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for MigrationError {}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for MigrationError {
+    fn from(e: bincode::Error) -> Self {
+        MigrationError::Serialization(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ValidationError> for MigrationError {
+    fn from(e: ValidationError) -> Self {
+        MigrationError::Invalid(e)
+    }
+}
+
+impl From<SignatureError> for TransactionError {
+    fn from(e: SignatureError) -> Self {
+        TransactionError::Crypto(e)
+    }
+}
+
+// Why a transaction was refused by the mempool
+#[derive(Debug)]
+pub enum MempoolError {
+    Full,
+    Duplicate(Hash),
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::Full => write!(f, "mempool is full"),
+            MempoolError::Duplicate(id) => {
+                write!(f, "transaction {} is already pending", id)
+            }
+            MempoolError::Invalid(e) => {
+                write!(f, "invalid transaction: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+impl From<ValidationError> for MempoolError {
+    fn from(e: ValidationError) -> Self {
+        MempoolError::Invalid(e)
+    }
+}
+
+// Why transactions could not be written to or read from CSV
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(csv::Error),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Csv(e) => write!(f, "csv error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for CsvError {}
+
+#[cfg(feature = "serde")]
+impl From<csv::Error> for CsvError {
+    fn from(e: csv::Error) -> Self {
+        CsvError::Csv(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::Csv(e.into())
+    }
+}
+
+// Why an audit log could not be written or did not verify
+#[cfg(feature = "serde_json")]
+#[derive(Debug)]
+pub enum AuditError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Invalid(ValidationError),
+    OutOfSequence { expected: u64, found: u64 },
+    BrokenChain { seq: u64 },
+}
+
+#[cfg(feature = "serde_json")]
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Io(e) => write!(f, "io error: {}", e),
+            AuditError::Json(e) => write!(f, "json error: {}", e),
+            AuditError::Invalid(e) => write!(f, "invalid chain: {}", e),
+            AuditError::OutOfSequence { expected, found } => write!(
+                f,
+                "expected audit entry {} but found {}",
+                expected, found
+            ),
+            AuditError::BrokenChain { seq } => {
+                write!(
+                    f,
+                    "audit entry {} does not follow the one before",
+                    seq
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl std::error::Error for AuditError {}
+
+#[cfg(feature = "serde_json")]
+impl From<io::Error> for AuditError {
+    fn from(e: io::Error) -> Self {
+        AuditError::Io(e)
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for AuditError {
+    fn from(e: serde_json::Error) -> Self {
+        AuditError::Json(e)
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl From<ValidationError> for AuditError {
+    fn from(e: ValidationError) -> Self {
+        AuditError::Invalid(e)
+    }
+}
+
+// Why a batch of JSON transactions could not be imported
+#[cfg(feature = "serde_json")]
+#[derive(Debug)]
+pub enum ImportError {
+    Json(serde_json::Error), // Not a JSON array
+    InvalidField {
+        index: usize, // Of the transaction in the array, from 0
+        field: &'static str,
+    },
+}
+
+#[cfg(feature = "serde_json")]
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Json(e) => write!(f, "json error: {}", e),
+            ImportError::InvalidField { index, field } => {
+                write!(f, "transaction {} has an invalid {}", index, field)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl std::error::Error for ImportError {}
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for ImportError {
+    fn from(e: serde_json::Error) -> Self {
+        ImportError::Json(e)
+    }
+}
+
+// Why a checkpoint could not be confirmed
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheckpointError {
+    MissingBlock(u32), // Beyond the tip or pruned
+    HashMismatch {
+        index: u32,
+        expected: Hash,
+        got: Hash,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::MissingBlock(index) => {
+                write!(f, "no block {} to check", index)
+            }
+            CheckpointError::HashMismatch {
+                index,
+                expected,
+                got,
+            } => write!(
+                f,
+                "checkpoint at block {} expected hash {}, got {}",
+                index, expected, got
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+// Why a chain reorganization was refused
+#[derive(Debug)]
+pub enum ReorgError {
+    ShorterChain,
+    InvalidChain(ValidationError),
+    NoCommonAncestor,
+}
+
+impl fmt::Display for ReorgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReorgError::ShorterChain => {
+                write!(f, "new chain is not longer than the current chain")
+            }
+            ReorgError::InvalidChain(e) => {
+                write!(f, "new chain is invalid: {}", e)
+            }
+            ReorgError::NoCommonAncestor => {
+                write!(
+                    f,
+                    "new chain shares no blocks with the current chain"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReorgError {}
+
+impl From<ValidationError> for ReorgError {
+    fn from(e: ValidationError) -> Self {
+        ReorgError::InvalidChain(e)
+    }
+}
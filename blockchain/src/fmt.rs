@@ -0,0 +1,125 @@
+use crate::util::bytes_to_hex;
+use crate::{display_amount, Block, Blockchain, Txn, TxnKind, User};
+use std::fmt;
+
+// Milliseconds since the Unix epoch as an ISO-8601 UTC date-time
+pub fn to_iso8601(millis: u128) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        millis % 1000
+    )
+}
+
+impl fmt::Display for TxnKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxnKind::Transfer => write!(f, "transfer"),
+            TxnKind::CoinbaseReward { block_index } => {
+                write!(f, "coinbase reward for block {}", block_index)
+            }
+            TxnKind::MultiSig {
+                required,
+                signatories,
+            } => write!(
+                f,
+                "{}-of-{} multi-sig transfer",
+                required,
+                signatories.len()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Txn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "txn {} ({})", self.to_hex_id(), self.kind)?;
+        writeln!(f, "  from:      {}", self.sender)?;
+        writeln!(f, "  to:        {}", self.recipient)?;
+        writeln!(f, "  amount:    {}", display_amount(self.amount))?;
+        writeln!(f, "  fee:       {}", display_amount(self.fee))?;
+        writeln!(f, "  time:      {}", to_iso8601(self.timestamp))?;
+        if let Some(expiry) = self.expiry {
+            writeln!(f, "  expires:   {}", to_iso8601(expiry as u128))?;
+        }
+        if let Some(metadata) = &self.metadata {
+            writeln!(f, "  metadata:  {}", bytes_to_hex(metadata))?;
+        }
+        write!(f, "  signature: {}", bytes_to_hex(&self.signature))
+    }
+}
+
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "user {} {} key {} (created {})",
+            self.uid,
+            self.to_hex_address(),
+            self.public_key_hex(),
+            to_iso8601(self.timestamp)
+        )
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "block {} {}", self.index, self.to_hex_hash())?;
+        writeln!(f, "  prev:        {}", self.prev_hash)?;
+        writeln!(f, "  merkle root: {}", self.txns.merkle_root)?;
+        write!(f, "  time:        {}", to_iso8601(self.timestamp))?;
+        for txn in self.txns.iter() {
+            write!(f, "\n{}", txn)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "blockchain of {} blocks (created {})",
+            self.blocks.len(),
+            to_iso8601(self.timestamp)
+        )?;
+        for block in &self.blocks {
+            write!(f, "\n{}", block)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_iso8601_utc() {
+        assert_eq!(to_iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            to_iso8601(951_782_400_123),
+            "2000-02-29T00:00:00.123Z"
+        );
+    }
+}
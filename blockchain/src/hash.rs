@@ -0,0 +1,163 @@
+#[cfg(feature = "serde")]
+use crate::hex;
+use crate::hex::HexError;
+use crate::util::{bytes_to_hex, hex_to_bytes};
+use crate::HASH_SIZE;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::borrow::Borrow;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::{Hash as StdHash, Hasher};
+use std::str::FromStr;
+
+// Which hash function a chain uses for its block hashes, transaction ids
+// and merkle trees
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256, // For interoperating with SHA-256 based systems
+}
+
+impl HashAlgorithm {
+    pub fn digest(&self, input: &[u8]) -> Hash {
+        match self {
+            HashAlgorithm::Blake3 => blake3_to_bytes(input),
+            HashAlgorithm::Sha256 => sha256_to_bytes(input),
+        }
+    }
+}
+
+pub fn blake3_to_bytes(input: &[u8]) -> Hash {
+    blake3::hash(input).into()
+}
+
+pub fn sha256_to_bytes(input: &[u8]) -> Hash {
+    let mut bytes = [0; HASH_SIZE];
+    bytes.copy_from_slice(&Sha256::digest(input));
+    Hash(bytes)
+}
+
+// A hash of a block, transaction or merkle node, kept distinct
+// from addresses and plain byte arrays
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Hash(pub [u8; HASH_SIZE]);
+
+// Hashes exactly like the bytes it borrows as, so maps keyed by Hash can
+// be searched with a plain byte slice
+impl StdHash for Hash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0[..].hash(state);
+    }
+}
+
+impl Borrow<[u8]> for Hash {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Hash {
+    // 64 hex digits in either case, as written by Display
+    pub fn from_hex(s: &str) -> Result<Hash, HexError> {
+        let bytes = hex_to_bytes(s)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map(Hash)
+            .map_err(|_| HexError::InvalidLength {
+                expected: HASH_SIZE,
+                got: len,
+            })
+    }
+
+    // A measure of the work that went into finding it
+    pub fn leading_zero_bits(&self) -> u32 {
+        let zero_bytes = self.0.iter().take_while(|&&b| b == 0).count();
+        let rest = self.0.get(zero_bytes).map_or(0, |b| b.leading_zeros());
+        zero_bytes as u32 * 8 + rest
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", bytes_to_hex(&self.0))
+    }
+}
+
+impl FromStr for Hash {
+    type Err = HexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hash::from_hex(s)
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<blake3::Hash> for Hash {
+    fn from(hash: blake3::Hash) -> Self {
+        Hash(*hash.as_bytes())
+    }
+}
+
+// Hex in JSON, raw bytes in bincode
+#[cfg(feature = "serde")]
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        hex::serialize(&self.0, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        hex::deserialize(deserializer).map(Hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_algorithms_digest_known_input() {
+        assert_eq!(
+            sha256_to_bytes(b"abc").to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            blake3_to_bytes(b"abc"),
+            HashAlgorithm::Blake3.digest(b"abc")
+        );
+    }
+
+    #[test]
+    fn leading_zero_bits_are_counted() {
+        assert_eq!(Hash::default().leading_zero_bits(), 256);
+        let mut bits = [0xff; HASH_SIZE];
+        bits[0] = 0x00;
+        bits[1] = 0x1f;
+        assert_eq!(Hash(bits).leading_zero_bits(), 11);
+    }
+
+    #[test]
+    fn hashes_parse_from_hex() {
+        let hash = blake3_to_bytes(b"abc");
+        assert_eq!(hash.to_string().parse::<Hash>(), Ok(hash));
+        assert!("zz".parse::<Hash>().is_err());
+        assert_eq!(Hash::default().as_ref(), &[0; HASH_SIZE][..]);
+    }
+}
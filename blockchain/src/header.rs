@@ -0,0 +1,182 @@
+use crate::encode::Encode;
+use crate::hash::HashAlgorithm;
+use crate::merkle::MerkleProof;
+use crate::validation::ValidationError;
+use crate::Hash;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// A block without its transactions, for light clients. The block hash
+// covers exactly these fields; the transactions are committed to through
+// the merkle root.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub hash: Hash,
+    pub prev_hash: Hash,
+    pub merkle_root: Hash,
+    pub index: u32,
+    pub timestamp: u128,
+    pub nonce: u64,
+}
+
+impl BlockHeader {
+    pub fn calc_hash(&self, algorithm: &HashAlgorithm) -> Hash {
+        let mut bytes = Vec::new();
+        self.prev_hash.encode(&mut bytes);
+        self.merkle_root.encode(&mut bytes);
+        self.index.encode(&mut bytes);
+        self.timestamp.encode(&mut bytes);
+        self.nonce.encode(&mut bytes);
+        algorithm.digest(&bytes)
+    }
+
+    pub fn verify_hash(&self) -> bool {
+        self.verify_hash_with(&HashAlgorithm::default())
+    }
+
+    pub fn verify_hash_with(&self, algorithm: &HashAlgorithm) -> bool {
+        self.calc_hash(algorithm) == self.hash
+    }
+
+    // True if this header comes straight after `other`
+    pub fn chain_connects_to(&self, other: &BlockHeader) -> bool {
+        self.prev_hash == other.hash
+            && other.index.checked_add(1) == Some(self.index)
+    }
+}
+
+// The headers of a chain, oldest first. Enough to check that the chain
+// hangs together and that a transaction is in one of its blocks, but not
+// proof of work or balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+}
+
+impl HeaderChain {
+    pub fn new(headers: Vec<BlockHeader>) -> Self {
+        Self { headers }
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&BlockHeader> {
+        self.headers.iter().find(|header| header.hash == *hash)
+    }
+
+    pub fn verify(&self) -> Result<(), ValidationError> {
+        self.verify_with(&HashAlgorithm::default())
+    }
+
+    // Every hash must match its header and every header must follow the
+    // one before it. The first header may follow anything, so a chain
+    // can start after pruned blocks.
+    pub fn verify_with(
+        &self,
+        algorithm: &HashAlgorithm,
+    ) -> Result<(), ValidationError> {
+        for (i, header) in self.headers.iter().enumerate() {
+            if !header.verify_hash_with(algorithm) {
+                return Err(ValidationError::HashMismatch);
+            }
+            let prev = match i.checked_sub(1) {
+                Some(prev) => &self.headers[prev],
+                None => continue,
+            };
+            let expected = prev.index.saturating_add(1);
+            if header.index != expected {
+                return Err(ValidationError::UnexpectedIndex {
+                    expected,
+                    got: header.index,
+                });
+            }
+            if header.prev_hash != prev.hash {
+                return Err(ValidationError::BrokenChain {
+                    expected: prev.hash,
+                    got: header.prev_hash,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the proof, made from a block, puts the transaction in a
+    // block on this chain
+    pub fn contains_transaction(
+        &self,
+        txn_id: &Hash,
+        proof: &MerkleProof,
+    ) -> bool {
+        proof
+            .block
+            .and_then(|(hash, _)| self.get(&hash))
+            .is_some_and(|header| {
+                proof.verify(txn_id, &header.merkle_root)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::keyed_user;
+    use crate::txns::TxnsBuilder;
+    use crate::validation::ValidationError;
+    use crate::{Block, Blockchain, Hash, Txn};
+    use crate::{COIN, GENESIS_REWARD};
+
+    // Light clients check headers, and transactions against them
+    #[test]
+    fn header_chains_prove_transactions() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut chain = Blockchain::with_genesis(&user1);
+        let txns = TxnsBuilder::new()
+            .add_coinbase(&user2, GENESIS_REWARD, 1)
+            .add_transfer(&user1, &key1, &user2, COIN, 0)
+            .build()
+            .unwrap();
+        let genesis_hash = chain.tip().unwrap().hash;
+        chain.add_block(Block::new(genesis_hash, txns, 1)).unwrap();
+
+        let headers = chain.headers();
+        assert_eq!(headers.len(), 2);
+        assert!(headers.iter().all(|header| header.verify_hash()));
+        assert!(headers[1].chain_connects_to(&headers[0]));
+        assert!(!headers[0].chain_connects_to(&headers[1]));
+        let header_chain = Blockchain::from_headers(headers.clone());
+        assert!(header_chain.verify().is_ok());
+        assert_eq!(
+            header_chain.tip(),
+            Some(&chain.tip().unwrap().as_header())
+        );
+
+        let spv_block = &chain.blocks[1];
+        for txn in spv_block.txns.iter() {
+            let proof = spv_block.merkle_proof_for(&txn.id).unwrap();
+            assert!(header_chain.contains_transaction(&txn.id, &proof));
+        }
+        let stray = Txn::new(&user2, &user1, COIN);
+        let first_id: Hash = spv_block.txns.iter().next().unwrap().id;
+        let proof = spv_block.merkle_proof_for(&first_id).unwrap();
+        assert!(!header_chain.contains_transaction(&stray.id, &proof));
+
+        let mut forged_headers = headers;
+        forged_headers[1].nonce += 1;
+        assert!(!forged_headers[1].verify_hash());
+        assert_eq!(
+            Blockchain::from_headers(forged_headers).verify(),
+            Err(ValidationError::HashMismatch)
+        );
+    }
+}
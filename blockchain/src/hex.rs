@@ -0,0 +1,156 @@
+#[cfg(feature = "serde")]
+use crate::util::{bytes_to_hex, hex_to_bytes};
+#[cfg(feature = "serde")]
+use crate::HASH_SIZE;
+#[cfg(feature = "serde")]
+use serde::de::Error as _;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use std::convert::TryInto;
+use std::fmt;
+
+// Why a string is not valid hex
+#[derive(Debug, PartialEq, Eq)]
+pub enum HexError {
+    OddLength,
+    InvalidDigit(char),
+    InvalidLength { expected: usize, got: usize },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexError::OddLength => write!(f, "odd number of hex digits"),
+            HexError::InvalidDigit(c) => {
+                write!(f, "invalid hex digit {:?}", c)
+            }
+            HexError::InvalidLength { expected, got } => {
+                write!(f, "expected {} bytes, got {}", expected, got)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+// Serde helpers for fixed-size byte arrays, used by Hash and Address.
+// Human-readable formats like JSON get a hex string; bincode gets the
+// raw bytes, exactly as a plain array would.
+#[cfg(feature = "serde")]
+pub fn serialize<S: Serializer>(
+    bytes: &[u8; HASH_SIZE],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&bytes_to_hex(bytes))
+    } else {
+        bytes.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[u8; HASH_SIZE], D::Error> {
+    if deserializer.is_human_readable() {
+        let s = String::deserialize(deserializer)?;
+        let bytes = hex_to_bytes(&s).map_err(D::Error::custom)?;
+        let len = bytes.len();
+        bytes.try_into().map_err(|_| {
+            D::Error::custom(HexError::InvalidLength {
+                expected: HASH_SIZE,
+                got: len,
+            })
+        })
+    } else {
+        <[u8; HASH_SIZE]>::deserialize(deserializer)
+    }
+}
+
+// The same, for variable-length byte vectors like signatures
+#[cfg(feature = "serde")]
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&bytes_to_hex(bytes))
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            hex_to_bytes(&s).map_err(D::Error::custom)
+        } else {
+            Vec::<u8>::deserialize(deserializer)
+        }
+    }
+}
+
+// The same, for optional byte vectors like transaction metadata
+#[cfg(feature = "serde")]
+pub mod option_vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            bytes.as_deref().map(bytes_to_hex).serialize(serializer)
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        if deserializer.is_human_readable() {
+            Option::<String>::deserialize(deserializer)?
+                .map(|s| hex_to_bytes(&s).map_err(D::Error::custom))
+                .transpose()
+        } else {
+            Option::<Vec<u8>>::deserialize(deserializer)
+        }
+    }
+}
+
+// The same, for ed25519 public keys
+#[cfg(feature = "serde")]
+pub mod public_key {
+    use super::*;
+    use ed25519_dalek::PublicKey;
+
+    pub fn serialize<S: Serializer>(
+        key: &PublicKey,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&bytes_to_hex(key.as_bytes()))
+        } else {
+            key.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<PublicKey, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            let bytes = hex_to_bytes(&s).map_err(D::Error::custom)?;
+            PublicKey::from_bytes(&bytes).map_err(D::Error::custom)
+        } else {
+            PublicKey::deserialize(deserializer)
+        }
+    }
+}
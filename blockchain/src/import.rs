@@ -0,0 +1,139 @@
+use crate::error::ImportError;
+use crate::{Address, Blockchain, Hash, Txn, TxnKind, SIGNATURE_SIZE};
+use ed25519_dalek::Signature;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+type FieldCheck = fn(&Value) -> bool;
+
+fn parses<T: DeserializeOwned>(value: &Value) -> bool {
+    T::deserialize(value).is_ok()
+}
+
+// Every field of a transaction in its JSON form, from Txn::to_json, with a
+// check that it reads back on its own. A missing field is read as null,
+// which only the optional fields accept.
+const FIELDS: [(&str, FieldCheck); 11] = [
+    ("version", parses::<u8>),
+    ("id", parses::<Hash>),
+    ("kind", parses::<TxnKind>),
+    ("sender", parses::<Address>),
+    ("recipient", parses::<Address>),
+    ("amount", parses::<u64>),
+    ("fee", parses::<u64>),
+    ("timestamp", parses::<u128>),
+    ("expiry", parses::<Option<u64>>),
+    ("metadata", |value| {
+        crate::hex::option_vec::deserialize(value).is_ok()
+    }),
+    ("signature", |value| {
+        crate::hex::vec::deserialize(value).is_ok()
+    }),
+];
+
+impl Blockchain {
+    // Read a JSON array of transactions, such as a batch from a wallet,
+    // checking that each is well formed: it reads back, moves a non-zero
+    // amount, has the id its contents hash to and carries signatures that
+    // parse. Whether the signatures are valid, or the sender can afford
+    // it, is left to whoever takes the transactions in.
+    pub fn import_json_transactions(
+        json: &str,
+    ) -> Result<Vec<Txn>, ImportError> {
+        let values: Vec<Value> = serde_json::from_str(json)?;
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| import_transaction(index, value))
+            .collect()
+    }
+}
+
+fn import_transaction(
+    index: usize,
+    value: Value,
+) -> Result<Txn, ImportError> {
+    let invalid = |field| ImportError::InvalidField { index, field };
+    let object = value.as_object().ok_or(invalid("transaction"))?;
+    if let Some((field, _)) = FIELDS.iter().find(|(field, check)| {
+        !check(object.get(*field).unwrap_or(&Value::Null))
+    }) {
+        return Err(invalid(field));
+    }
+    let txn: Txn = serde_json::from_value(value)
+        .map_err(|_| invalid("transaction"))?;
+
+    if txn.amount == 0 {
+        return Err(invalid("amount"));
+    }
+    if !txn.verify_id() {
+        return Err(invalid("id"));
+    }
+    if txn.signature.is_empty()
+        || !txn.signature.len().is_multiple_of(SIGNATURE_SIZE)
+        || txn
+            .signature
+            .chunks(SIGNATURE_SIZE)
+            .any(|sig| Signature::from_bytes(sig).is_err())
+    {
+        return Err(invalid("signature"));
+    }
+    Ok(txn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::keyed_user;
+    use crate::{Json, COIN};
+
+    #[test]
+    fn transactions_are_checked_field_by_field() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let signed = Txn::new_signed(&user1, &key1, &user2, COIN);
+        let signed_json = signed.to_json().unwrap();
+        let mut tagged =
+            signed.clone().with_metadata(b"bridge:42").unwrap();
+        tagged.sign(&key1);
+
+        let batch =
+            format!("[{},{}]", tagged.to_json().unwrap(), signed_json);
+        let imported =
+            Blockchain::import_json_transactions(&batch).unwrap();
+        assert_eq!(imported, vec![tagged, signed]);
+        assert!(Blockchain::import_json_transactions("[]")
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            Blockchain::import_json_transactions("{}"),
+            Err(ImportError::Json(_))
+        ));
+
+        let mut unfunded = Txn::new(&user1, &user2, 0);
+        unfunded.sign(&key1);
+        let unsigned = Txn::new(&user1, &user2, COIN);
+        let mut retimed: Value =
+            serde_json::from_str(&signed_json).unwrap();
+        retimed["timestamp"] = serde_json::json!(0);
+        let mut truncated = retimed.clone();
+        truncated["sender"] = serde_json::json!("00ab");
+        let mut unsent = retimed.clone();
+        unsent.as_object_mut().unwrap().remove("recipient");
+        for (json, bad_field) in [
+            (unfunded.to_json().unwrap(), "amount"),
+            (unsigned.to_json().unwrap(), "signature"),
+            (retimed.to_string(), "id"),
+            (truncated.to_string(), "sender"),
+            (unsent.to_string(), "recipient"),
+            (String::from("7"), "transaction"),
+        ] {
+            let batch = format!("[{},{}]", signed_json, json);
+            assert!(matches!(
+                Blockchain::import_json_transactions(&batch),
+                Err(ImportError::InvalidField { index: 1, field })
+                    if field == bad_field
+            ));
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::error::KeyError;
+use crate::validate_uid;
+use ed25519_dalek::{
+    Keypair, SecretKey, KEYPAIR_LENGTH, SECRET_KEY_LENGTH,
+};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
+
+const KEY_EXTENSION: &str = "priv";
+const KEY_FILE_VERSION: u8 = 1;
+const CHECKSUM_SIZE: usize = 4;
+const KEY_FILE_SIZE: usize = 1 + CHECKSUM_SIZE + KEYPAIR_LENGTH;
+// Files from before there was a version byte end in a blake3 hash
+const LEGACY_CHECKSUM_SIZE: usize = 32;
+
+// A keypair whose secret half is wiped when it is dropped
+pub struct ZeroizingKeypair(Keypair);
+
+impl Zeroize for ZeroizingKeypair {
+    fn zeroize(&mut self) {
+        // The replaced SecretKey clears its own bytes as it is dropped
+        self.0.secret = SecretKey::from_bytes(&[0; SECRET_KEY_LENGTH])
+            .expect("Zero bytes are a valid secret key");
+    }
+}
+
+impl Drop for ZeroizingKeypair {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl From<Keypair> for ZeroizingKeypair {
+    fn from(keypair: Keypair) -> Self {
+        ZeroizingKeypair(keypair)
+    }
+}
+
+impl Deref for ZeroizingKeypair {
+    type Target = Keypair;
+
+    fn deref(&self) -> &Keypair {
+        &self.0
+    }
+}
+
+// A keypair as it is written to disk: a version byte, then the first 4
+// bytes of the SHA-256 of the keypair, then the keypair. The keypair is
+// wiped when it is dropped.
+pub struct KeyFile {
+    version: u8,
+    checksum: [u8; CHECKSUM_SIZE],
+    payload: [u8; KEYPAIR_LENGTH],
+}
+
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        self.payload.zeroize();
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut checksum = [0; CHECKSUM_SIZE];
+    checksum.copy_from_slice(&Sha256::digest(payload)[..CHECKSUM_SIZE]);
+    checksum
+}
+
+impl KeyFile {
+    fn new(keypair: &Keypair) -> Self {
+        let mut file = KeyFile {
+            version: KEY_FILE_VERSION,
+            checksum: [0; CHECKSUM_SIZE],
+            payload: keypair.to_bytes(),
+        };
+        file.checksum = checksum(&file.payload);
+        file
+    }
+
+    fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(KEY_FILE_SIZE));
+        bytes.push(self.version);
+        bytes.extend_from_slice(&self.checksum);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        match bytes.first() {
+            Some(&KEY_FILE_VERSION) => {}
+            Some(&version) => {
+                return Err(KeyError::UnsupportedVersion(version))
+            }
+            None => return Err(KeyError::InvalidLength(0)),
+        }
+        if bytes.len() != KEY_FILE_SIZE {
+            return Err(KeyError::InvalidLength(bytes.len()));
+        }
+        let mut file = KeyFile {
+            version: bytes[0],
+            checksum: [0; CHECKSUM_SIZE],
+            payload: [0; KEYPAIR_LENGTH],
+        };
+        file.checksum.copy_from_slice(&bytes[1..1 + CHECKSUM_SIZE]);
+        file.payload.copy_from_slice(&bytes[1 + CHECKSUM_SIZE..]);
+        if checksum(&file.payload) != file.checksum {
+            return Err(KeyError::ChecksumMismatch);
+        }
+        Ok(file)
+    }
+
+    pub fn save<P: AsRef<Path>>(
+        path: P,
+        keypair: &Keypair,
+    ) -> io::Result<()> {
+        let bytes = KeyFile::new(keypair).to_bytes();
+        let mut f = File::create(path)?;
+        f.write_all(&bytes)?;
+        f.sync_all()
+    }
+
+    // Also reads files written before there was a version byte
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Keypair, KeyError> {
+        let mut buffer = Zeroizing::new(Vec::new());
+        File::open(path)?.read_to_end(&mut buffer)?;
+
+        if buffer.len() == KEYPAIR_LENGTH + LEGACY_CHECKSUM_SIZE {
+            let (key_bytes, checksum) = buffer.split_at(KEYPAIR_LENGTH);
+            if blake3::hash(key_bytes).as_bytes() != checksum {
+                return Err(KeyError::ChecksumMismatch);
+            }
+            return Ok(Keypair::from_bytes(key_bytes)?);
+        }
+        let file = KeyFile::from_bytes(&buffer)?;
+        Ok(Keypair::from_bytes(&file.payload)?)
+    }
+}
+
+// A directory of keypairs, one `{uid}.priv` key file per user. Invalid
+// uids are never looked up on disk.
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, uid: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", uid, KEY_EXTENSION))
+    }
+
+    // Write to a temp file first, then rename it into place, so an
+    // existing key is never left half-overwritten
+    pub fn insert(&self, uid: &str, keypair: &Keypair) -> io::Result<()> {
+        check_uid(uid)?;
+        let tmp_path =
+            self.dir.join(format!("{}.{}.tmp", uid, KEY_EXTENSION));
+        KeyFile::save(&tmp_path, keypair)?;
+        fs::rename(tmp_path, self.path(uid))
+    }
+
+    // None if there is no key for the uid. A key file that is there but
+    // does not read back, such as one failing its checksum, is an error.
+    pub fn get(
+        &self,
+        uid: &str,
+    ) -> Result<Option<ZeroizingKeypair>, KeyError> {
+        if validate_uid(uid).is_err() {
+            return Ok(None);
+        }
+        match KeyFile::load(self.path(uid)) {
+            Ok(keypair) => Ok(Some(ZeroizingKeypair(keypair))),
+            Err(KeyError::Io(e))
+                if e.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn remove(&self, uid: &str) -> io::Result<()> {
+        check_uid(uid)?;
+        fs::remove_file(self.path(uid))
+    }
+
+    // The uids of every stored key, sorted
+    pub fn list(&self) -> Vec<String> {
+        let mut uids: Vec<String> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|ext| ext == KEY_EXTENSION)
+                })
+                .filter_map(|path| {
+                    path.file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .map(String::from)
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        uids.sort();
+        uids
+    }
+
+    pub fn contains(&self, uid: &str) -> bool {
+        validate_uid(uid).is_ok() && self.path(uid).is_file()
+    }
+}
+
+// validate_uid, for the methods that report i/o errors
+fn check_uid(uid: &str) -> io::Result<()> {
+    validate_uid(uid).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{keyed_user, temp_cfg};
+
+    // Key files carry a version and a checksum, and older files without
+    // a version still load
+    #[test]
+    fn key_files_are_versioned_and_checked() {
+        let (_, keypair) = keyed_user("user1");
+        let (_dir, cfg) = temp_cfg();
+        let key_path = cfg.secret_dir.join("check.priv");
+        KeyFile::save(&key_path, &keypair).unwrap();
+        let mut key_bytes = fs::read(&key_path).unwrap();
+        assert_eq!(key_bytes.len(), KEY_FILE_SIZE);
+        assert_eq!(key_bytes[0], KEY_FILE_VERSION);
+        assert_eq!(
+            KeyFile::load(&key_path).unwrap().to_bytes()[..],
+            keypair.to_bytes()[..]
+        );
+
+        key_bytes[10] ^= 1;
+        fs::write(&key_path, &key_bytes).unwrap();
+        assert!(matches!(
+            KeyFile::load(&key_path),
+            Err(KeyError::ChecksumMismatch)
+        ));
+        key_bytes[0] = 9;
+        fs::write(&key_path, &key_bytes).unwrap();
+        assert!(matches!(
+            KeyFile::load(&key_path),
+            Err(KeyError::UnsupportedVersion(9))
+        ));
+
+        let mut legacy = keypair.to_bytes().to_vec();
+        legacy.extend_from_slice(blake3::hash(&legacy).as_bytes());
+        fs::write(&key_path, &legacy).unwrap();
+        assert_eq!(
+            KeyFile::load(&key_path).unwrap().public,
+            keypair.public
+        );
+    }
+
+    #[test]
+    fn stores_list_the_keys_they_hold() {
+        let (_, keypair) = keyed_user("user1");
+        let (_dir, cfg) = temp_cfg();
+        let store = KeyStore::new(cfg.secret_dir);
+        store.insert("user1", &keypair).unwrap();
+        assert!(store.contains("user1"));
+        assert_eq!(
+            store.get("user1").unwrap().unwrap().public,
+            keypair.public
+        );
+        assert!(store.list().contains(&String::from("user1")));
+        assert!(store.insert("../user1", &keypair).is_err());
+
+        // A corrupted key is reported, not taken for a missing one
+        let mut key_bytes = fs::read(store.path("user1")).unwrap();
+        key_bytes[10] ^= 1;
+        fs::write(store.path("user1"), &key_bytes).unwrap();
+        assert!(matches!(
+            store.get("user1"),
+            Err(KeyError::ChecksumMismatch)
+        ));
+        store.remove("user1").unwrap();
+        assert!(!store.contains("user1"));
+        assert!(store.get("user1").unwrap().is_none());
+    }
+}
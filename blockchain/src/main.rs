@@ -1,104 +1,427 @@
-use blake3;
-use ed25519_dalek::{Keypair, PublicKey, Signature};
+#![allow(dead_code)]
+
+mod address;
+#[cfg(feature = "serde_json")]
+mod audit;
+mod encode;
+mod error;
+#[cfg(feature = "serde")]
+mod export;
+mod fmt;
+mod hash;
+mod header;
+mod hex;
+#[cfg(feature = "serde_json")]
+mod import;
+mod keystore;
+mod mempool;
+mod merkle;
+#[cfg(feature = "serde")]
+mod migration;
+mod partial;
+mod storage;
+mod txns;
+mod util;
+mod validation;
+mod walker;
+
+use address::Address;
+use ed25519_dalek::{Keypair, PublicKey, Signature, PUBLIC_KEY_LENGTH};
+use encode::Encode;
+use error::{
+    BlockchainError, CheckpointError, ReorgError, TransactionError,
+    UserError,
+};
+#[cfg(feature = "serde")]
+use error::{MigrationError, StorageError};
+use hash::{Hash, HashAlgorithm};
+use header::{BlockHeader, HeaderChain};
+use hex::HexError;
+use keystore::{KeyStore, ZeroizingKeypair};
+use merkle::MerkleProof;
+#[cfg(feature = "serde")]
+use migration::TxnMigration;
 use rand::rngs::OsRng;
 use rand::Rng;
+#[cfg(feature = "serde_json")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use sha2::Sha512;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "serde")]
+use std::fs::{self, File};
+#[cfg(feature = "serde")]
 use std::io::prelude::*;
-use std::time::Instant;
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use storage::StorageConfig;
+use txns::Txns;
+use validation::ValidationError;
+use walker::ChainWalker;
 
 const HASH_SIZE: usize = 32;
-type Hash = [u8; HASH_SIZE];
-type Address = Hash;
 
-#[derive(Debug)]
-enum Valid {
-    Valid,
-    Invalid,
+// Amounts are integers in the smallest unit; one coin is 10^DECIMALS
+const DECIMALS: u32 = 8;
+const COIN: u64 = 10u64.pow(DECIMALS);
+const GENESIS_REWARD: u64 = 50 * COIN;
+const MAX_SUPPLY: u64 = 21_000_000 * COIN;
+
+// Difficulty is retargeted every so many blocks, towards one block per
+// TARGET_BLOCK_MILLIS
+const DEFAULT_RETARGET_INTERVAL: u32 = 10;
+const TARGET_BLOCK_MILLIS: u128 = 60_000;
+
+// Layout of a chain file's payload, written as a u32 before it
+const CHAIN_SCHEMA_VERSION: u32 = 2;
+
+// How many nonces a migration tries for each block it mines again, before
+// giving up on a chain whose difficulty is too high to bring up to date
+const MAX_REMINE_ATTEMPTS: u64 = 1 << 24;
+
+// How many blocks an orphan waits for its parent before it is dropped
+const DEFAULT_ORPHAN_TTL: u32 = 100;
+
+// How far ahead of our clock a block timestamp may be
+const MAX_FUTURE_DRIFT_MILLIS: u128 = 2 * 60 * 60 * 1000;
+
+// Format a raw amount as a decimal number of coins
+fn display_amount(raw: u64) -> String {
+    format!(
+        "{}.{:0width$}",
+        raw / COIN,
+        raw % COIN,
+        width = DECIMALS as usize
+    )
 }
 
-fn gen_nonce() -> f64 {
-    rand::thread_rng().gen::<f64>()
+// Milliseconds since the Unix epoch
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is before the Unix epoch")
+        .as_millis()
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// Nonces used to be f64. Both are 8 bytes in bincode, so files written
+// with the old type still load; the old bits just read as a u64.
+fn gen_nonce() -> Result<u64, rand::Error> {
+    Ok(OsRng::new()?.gen::<u64>())
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct User {
     address: Address,
     timestamp: u128,
-    nonce: f64,
+    nonce: u64,
+    #[cfg_attr(feature = "serde", serde(with = "hex::public_key"))]
     public_key: PublicKey,
     uid: String,
 }
 
+// Uids become file names, so only 1 to 64 ASCII letters, digits, `_` and
+// `-` are allowed; nothing that could climb out of the directory
+fn validate_uid(uid: &str) -> Result<(), UserError> {
+    let valid_char =
+        |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if uid.is_empty()
+        || uid.len() > MAX_UID_LEN
+        || !uid.chars().all(valid_char)
+    {
+        return Err(UserError::InvalidUid(String::from(uid)));
+    }
+    Ok(())
+}
+
 impl User {
-    fn new(uid: &str) -> Self {
+    // The same as create_new
+    fn new(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Self, BlockchainError> {
+        User::create_new(uid, cfg)
+    }
+
+    // A user with a fresh keypair, unless the uid is already taken
+    fn create_new(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Self, BlockchainError> {
+        validate_uid(uid)?;
+        if User::uid_exists(uid, cfg) {
+            return Err(UserError::AlreadyExists(String::from(uid)).into());
+        }
+        User::overwrite(uid, cfg)
+    }
+
+    // A user with a fresh keypair, replacing any stored key for the uid.
+    // A saved user file is only replaced once the user is written over it
+    // with to_disk.
+    fn overwrite(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Self, BlockchainError> {
+        validate_uid(uid)?;
         let mut user = Self {
-            address: [0; HASH_SIZE],
-            timestamp: Instant::now().elapsed().as_millis(),
-            nonce: gen_nonce(),
-            public_key: User::gen_keypair(uid).public,
+            address: Address::default(),
+            timestamp: now_millis(),
+            nonce: gen_nonce()?,
+            public_key: User::gen_keypair(uid, cfg)?.public,
             uid: String::from(uid),
         };
-        user.hash();
-        user
+        user.hash(&HashAlgorithm::default());
+        Ok(user)
     }
 
-    fn to_disk(&mut self) {
-        let mut f = File::create(format!("data/{}.user", self.uid))
-            .expect("Could not create user file");
-        f.write_all(
-            &bincode::serialize(self).expect("Could not serialize user")[..],
-        )
-        .expect("Could not write to user file");
+    // Someone known only by their public key, to send coins to. Nothing
+    // is read from or written to disk. The uid is the hex address.
+    fn from_public_key(pk: PublicKey) -> Self {
+        let address = Address::from_public_key(&pk);
+        Self {
+            address,
+            timestamp: 0,
+            nonce: 0,
+            public_key: pk,
+            uid: address.to_string(),
+        }
+    }
+
+    // True if there is no key for this user's uid in the configured key
+    // store, so it can receive but not sign
+    fn is_receive_only(&self, cfg: &StorageConfig) -> bool {
+        !KeyStore::new(&cfg.secret_dir).contains(&self.uid)
+    }
+
+    // True if there is a user file or a key for the uid, either of which
+    // creating the user again would overwrite
+    fn uid_exists(uid: &str, cfg: &StorageConfig) -> bool {
+        validate_uid(uid).is_ok()
+            && (User::path(uid, cfg).is_file()
+                || KeyStore::new(&cfg.secret_dir).contains(uid))
+    }
+
+    // Only for uids that have been through validate_uid
+    fn path(uid: &str, cfg: &StorageConfig) -> PathBuf {
+        cfg.data_dir.join(format!("{}.user", uid))
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_bytes(&self) -> Result<Vec<u8>, UserError> {
+        validate_uid(&self.uid)?;
+        Ok(bincode::serialize(self)?)
+    }
+
+    // Decode a user from untrusted bytes, checking its address. Users
+    // saved when the address was the hash of the whole user are given
+    // the address of their public key.
+    #[cfg(feature = "serde")]
+    fn from_bytes(bytes: &[u8]) -> Result<Self, UserError> {
+        let mut user: Self = bincode::deserialize(bytes)?;
+        validate_uid(&user.uid)?;
+        if user.address == user.legacy_address() {
+            user.hash(&HashAlgorithm::default());
+        }
+        if !user.verify_address() {
+            return Err(UserError::InvalidAddress);
+        }
+        Ok(user)
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_disk(&self, cfg: &StorageConfig) -> Result<(), BlockchainError> {
+        let bytes = self.to_bytes()?; // Checks the uid before it is a path
+        let mut f = File::create(User::path(&self.uid, cfg))?;
+        f.write_all(&storage::seal(&bytes))?;
+        Ok(())
     }
 
-    fn from_uid(uid: &str) -> Self {
-        let mut f = File::open(format!("data/{}.user", uid))
-            .expect("Could not open user file");
+    #[cfg(feature = "serde")]
+    fn from_uid(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Self, BlockchainError> {
+        validate_uid(uid)?;
+        let mut f = File::open(User::path(uid, cfg))?;
         let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer)
-            .expect("Could not read from user file");
+        f.read_to_end(&mut buffer)?;
 
-        let user: Self = bincode::deserialize(&buffer[..])
-            .expect("Could not deserialize user");
+        Ok(User::from_bytes(storage::unseal(&buffer)?)?)
+    }
 
-        user
+    // Load a user file written before files were sealed, which is the
+    // bare bytes of the user, and write it back sealed. Sealed files load
+    // as with from_uid.
+    #[cfg(feature = "serde")]
+    fn migrate_legacy_file(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Self, BlockchainError> {
+        validate_uid(uid)?;
+        let buffer = fs::read(User::path(uid, cfg))?;
+        if storage::is_sealed(&buffer) {
+            return User::from_uid(uid, cfg);
+        }
+        let user = User::from_bytes(&buffer)?;
+        user.to_disk(cfg)?;
+        Ok(user)
     }
 
-    fn gen_keypair(uid: &str) -> Keypair {
-        let mut csprng = OsRng::new().unwrap();
-        let keypair = Keypair::generate::<Sha512, _>(&mut csprng);
+    fn to_hex_address(&self) -> String {
+        util::bytes_to_hex(&self.address.0)
+    }
 
-        let mut f = File::create(format!("secret/{}.priv", uid))
-            .expect("Could not create user private key file");
-        f.write_all(&keypair.to_bytes());
+    // The 32 byte compressed public key, for showing to the user
+    fn public_key_hex(&self) -> String {
+        util::bytes_to_hex(self.public_key.as_bytes())
+    }
 
-        keypair
+    fn from_public_key_hex(hex: &str) -> Result<PublicKey, UserError> {
+        let bytes = util::hex_to_bytes(hex)?;
+        if bytes.len() != PUBLIC_KEY_LENGTH {
+            return Err(HexError::InvalidLength {
+                expected: PUBLIC_KEY_LENGTH,
+                got: bytes.len(),
+            }
+            .into());
+        }
+        Ok(PublicKey::from_bytes(&bytes)?)
     }
 
-    fn get_keypair(uid: &str) -> Keypair {
-        let mut f = File::open(format!("secret/{}.priv", uid))
-            .expect("Could not open secret file");
+    // The address is the hash of the public key
+    fn verify_address(&self) -> bool {
+        Address::from_public_key(&self.public_key) == self.address
+    }
 
-        let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer)
-            .expect("Could not read from secret file");
+    // What the address used to be: the hash of the whole user before it
+    // had an address
+    #[cfg(feature = "serde")]
+    fn legacy_address(&self) -> Address {
+        let unhashed = Self {
+            address: Address::default(),
+            uid: self.uid.clone(),
+            ..*self
+        };
+        let digest =
+            HashAlgorithm::default().digest(&unhashed.to_encoded());
+        Address(digest.0)
+    }
 
-        let keypair = Keypair::from_bytes(&buffer[..])
-            .expect("Could not deserialize secret");
+    // 0 if the user has never transacted on this chain
+    fn balance(&self, chain: &Blockchain) -> u64 {
+        chain.balance(&self.address).unwrap_or(0)
+    }
+
+    // Every transaction sent or received by this user, in chain order
+    fn transaction_history<'a>(
+        &self,
+        chain: &'a Blockchain,
+    ) -> Vec<&'a Txn> {
+        chain
+            .walk()
+            .filter_by_address(&self.address)
+            .map(|(_, txn)| txn)
+            .collect()
+    }
+
+    fn gen_keypair(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<Keypair, BlockchainError> {
+        validate_uid(uid)?;
+        let mut csprng = OsRng::new()?;
+        let keypair = Keypair::generate::<Sha512, _>(&mut csprng);
+
+        KeyStore::new(&cfg.secret_dir).insert(uid, &keypair)?;
+
+        Ok(keypair)
+    }
 
-        keypair
+    // A copy of the user with a fresh keypair, and so a new address. The
+    // new key replaces the stored one in a single rename, so if writing
+    // it fails the old key is still there. Coins held by the old address
+    // stay with it.
+    fn rotate_keypair(
+        &self,
+        store: &KeyStore,
+    ) -> Result<(User, ZeroizingKeypair), UserError> {
+        validate_uid(&self.uid)?;
+        let mut csprng = OsRng::new()?;
+        let keypair = Keypair::generate::<Sha512, _>(&mut csprng);
+        store.insert(&self.uid, &keypair)?;
+
+        let mut user = Self {
+            address: Address::default(),
+            public_key: keypair.public,
+            uid: self.uid.clone(),
+            ..*self
+        };
+        user.hash(&HashAlgorithm::default());
+        Ok((user, keypair.into()))
+    }
+
+    // Fails if the key is missing or its file is corrupted. The secret
+    // key is wiped once the returned keypair is dropped.
+    fn get_keypair(
+        uid: &str,
+        cfg: &StorageConfig,
+    ) -> Result<ZeroizingKeypair, BlockchainError> {
+        validate_uid(uid)?;
+        match KeyStore::new(&cfg.secret_dir).get(uid) {
+            Ok(Some(keypair)) => Ok(keypair),
+            Ok(None) => {
+                Err(BlockchainError::KeyNotFound(String::from(uid)))
+            }
+            Err(error) => Err(BlockchainError::InvalidKeyFile {
+                uid: String::from(uid),
+                error,
+            }),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// Layout of a serialized transaction. Version 0 had no version byte.
+const TXN_VERSION: u8 = 1;
+const SIGNATURE_SIZE: usize = 64;
+const MAX_UID_LEN: usize = 64;
+const MAX_METADATA_SIZE: usize = 256;
+const MAX_TXN_SIZE: usize = 10_000; // Serialized bytes
+const MAX_BLOCK_SIZE: usize = 1_000_000; // Serialized bytes
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TxnKind {
+    Transfer,
+    CoinbaseReward {
+        block_index: u32,
+    }, // Newly minted miner reward
+    // Spent from a shared address once `required` of the signatories
+    // have signed. The signatures are concatenated in `signature`.
+    MultiSig {
+        required: u8,
+        signatories: Vec<Address>,
+    },
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Txn {
+    version: u8, // Always first, so old layouts can be told apart
     id: Hash,
+    kind: TxnKind,
     sender: Address,
     recipient: Address,
-    amount: f64,
+    amount: u64,
+    fee: u64,
     timestamp: u128,
+    expiry: Option<u64>, // Unix ms after which it may not be mined
+    #[cfg_attr(feature = "serde", serde(with = "hex::option_vec"))]
+    metadata: Option<Vec<u8>>, // Application data, up to 256 bytes
+    #[cfg_attr(feature = "serde", serde(with = "hex::vec"))]
     signature: Vec<u8>,
 }
 
@@ -106,231 +429,4085 @@ trait CanSerialize {
     fn to_bytes(&self) -> Vec<u8>;
 }
 
+// Inspectable JSON, with byte strings as hex
+#[cfg(feature = "serde_json")]
+trait Json: Serialize + DeserializeOwned {
+    fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl Json for User {}
+#[cfg(feature = "serde_json")]
+impl Json for Txn {}
+#[cfg(feature = "serde_json")]
+impl Json for Block {}
+#[cfg(feature = "serde_json")]
+impl Json for BlockchainSnapshot {}
+
+#[cfg(feature = "serde_json")]
+impl Json for Blockchain {
+    fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        let mut blockchain: Self = serde_json::from_str(s)?;
+        blockchain.reindex();
+        Ok(blockchain)
+    }
+}
+
+// The same bytes bincode would write, with or without the serde feature
 impl CanSerialize for Txn {
     fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("Could not serialize transaction")
+        self.to_encoded()
     }
 }
 
 impl Txn {
-    fn new(sender: &User, recipient: &User, amount: f64) -> Self {
+    // Decode a transaction from untrusted bytes, checking its id. Bytes
+    // without a known version byte are read as version 0, which started
+    // straight with the id, so a version 0 id that happens to start with
+    // the current version byte is tried both ways.
+    #[cfg(feature = "serde")]
+    fn from_bytes(bytes: &[u8]) -> Result<Self, TransactionError> {
+        match bytes.first() {
+            Some(&TXN_VERSION) => Self::migrate(bytes, TXN_VERSION)
+                .or_else(|e| Self::migrate(bytes, 0).map_err(|_| e))
+                .map_err(TransactionError::from),
+            _ => Ok(Self::migrate(bytes, 0)?),
+        }
+    }
+
+    fn new(sender: &User, recipient: &User, amount: u64) -> Self {
+        Self::new_with(
+            sender,
+            recipient,
+            amount,
+            &HashAlgorithm::default(),
+        )
+    }
+
+    // The constructors ending in _with hash the id with the given
+    // algorithm, which must be the chain's
+    fn new_with(
+        sender: &User,
+        recipient: &User,
+        amount: u64,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
+        Self::new_to_address_with(
+            sender,
+            recipient.address,
+            amount,
+            algorithm,
+        )
+    }
+
+    fn new_to_address(
+        sender: &User,
+        recipient: Address,
+        amount: u64,
+    ) -> Self {
+        Self::new_to_address_with(
+            sender,
+            recipient,
+            amount,
+            &HashAlgorithm::default(),
+        )
+    }
+
+    fn new_to_address_with(
+        sender: &User,
+        recipient: Address,
+        amount: u64,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
         let mut txn = Self {
-            id: [0; HASH_SIZE],
+            version: TXN_VERSION,
+            id: Hash::default(),
+            kind: TxnKind::Transfer,
             sender: sender.address,
-            recipient: recipient.address,
+            recipient,
             amount,
-            timestamp: Instant::now().elapsed().as_millis(),
+            fee: 0,
+            timestamp: now_millis(),
+            expiry: None,
+            metadata: None,
             signature: Vec::new(),
         };
-        txn.hash();
+        txn.hash(algorithm);
+        txn
+    }
+
+    // A transfer that is hashed and signed before it is handed out. The
+    // keypair must be the sender's.
+    fn new_signed(
+        sender: &User,
+        keypair: &Keypair,
+        recipient: &User,
+        amount: u64,
+    ) -> Self {
+        Self::new_signed_with(
+            sender,
+            keypair,
+            recipient,
+            amount,
+            &HashAlgorithm::default(),
+        )
+    }
+
+    fn new_signed_with(
+        sender: &User,
+        keypair: &Keypair,
+        recipient: &User,
+        amount: u64,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
+        debug_assert_eq!(keypair.public, sender.public_key);
+        let mut txn = Self::new_with(sender, recipient, amount, algorithm);
+        txn.sign(keypair);
         txn
     }
 
-    // Needs the public key only
-    fn verify(&self, key: PublicKey) -> Valid {
-        let signature = Signature::from_bytes(&self.signature)
-            .expect("Invalid signature");
-        let no_sig = Self {
+    // Send part of the sender's balance and the rest to `change_address`,
+    // as two signed transfers. The change cannot go back to the sender
+    // itself, so it should be another address the sender controls, such
+    // as one from rotate_keypair. Fails unless something is left over.
+    fn split(
+        sender: &User,
+        keypair: &Keypair,
+        recipient: &User,
+        send_amount: u64,
+        change_address: &Address,
+        utxo_set: &HashMap<Address, u64>,
+    ) -> Result<(Txn, Txn), TransactionError> {
+        let available =
+            utxo_set.get(&sender.address).copied().unwrap_or(0);
+        if send_amount >= available {
+            return Err(ValidationError::InsufficientFunds {
+                sender: sender.address,
+                available,
+                requested: send_amount,
+            }
+            .into());
+        }
+        if *change_address == sender.address {
+            return Err(
+                ValidationError::SelfTransfer(sender.address).into()
+            );
+        }
+
+        let payment =
+            Self::new_signed(sender, keypair, recipient, send_amount);
+        let mut change = Self::new_to_address(
+            sender,
+            *change_address,
+            available - send_amount,
+        );
+        change.sign(keypair);
+        Ok((payment, change))
+    }
+
+    // A miner reward. It has no sender, so it is never signed.
+    fn new_coinbase(
+        recipient: &User,
+        reward: u64,
+        block_index: u32,
+    ) -> Self {
+        Self::new_coinbase_with(
+            recipient,
+            reward,
+            block_index,
+            &HashAlgorithm::default(),
+        )
+    }
+
+    fn new_coinbase_with(
+        recipient: &User,
+        reward: u64,
+        block_index: u32,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
+        let mut txn = Self {
+            version: TXN_VERSION,
+            id: Hash::default(),
+            kind: TxnKind::CoinbaseReward { block_index },
+            sender: Address::default(),
+            recipient: recipient.address,
+            amount: reward,
+            fee: 0,
+            timestamp: now_millis(),
+            expiry: None,
+            metadata: None,
             signature: Vec::new(),
-            ..*self
         };
-        let no_sig: &[u8] = &no_sig.to_bytes()[..];
+        txn.hash(algorithm);
+        txn
+    }
 
-        match key.verify::<Sha512>(no_sig, &signature) {
-            Ok(_) => return Valid::Valid,
-            Err(_) => return Valid::Invalid,
-        }
+    // A transfer out of a shared address, signed with add_signature
+    fn new_multisig(
+        sender: Address,
+        recipient: &User,
+        amount: u64,
+        required: u8,
+        signatories: Vec<Address>,
+    ) -> Self {
+        Self::new_multisig_with(
+            sender,
+            recipient,
+            amount,
+            required,
+            signatories,
+            &HashAlgorithm::default(),
+        )
     }
 
-    // Needs the private key
-    fn sign(&mut self, key: &Keypair) {
-        let self_bytes = &self.to_bytes()[..]; // Serialize self
-        let signature = key.sign::<Sha512>(self_bytes); // Calc the signature
-        self.signature = signature.to_bytes().to_vec(); // Set the signature
+    fn new_multisig_with(
+        sender: Address,
+        recipient: &User,
+        amount: u64,
+        required: u8,
+        signatories: Vec<Address>,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
+        let mut txn = Self {
+            version: TXN_VERSION,
+            id: Hash::default(),
+            kind: TxnKind::MultiSig {
+                required,
+                signatories,
+            },
+            sender,
+            recipient: recipient.address,
+            amount,
+            fee: 0,
+            timestamp: now_millis(),
+            expiry: None,
+            metadata: None,
+            signature: Vec::new(),
+        };
+        txn.hash(algorithm);
+        txn
     }
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Txns {
-    txns: Vec<Txn>,
-    merkle_root: Hash,
-}
+    // The algorithm the id was hashed with, so that changing a field
+    // rehashes with the same one. An id that matches none of them, such as
+    // a stale one, is taken to use the default.
+    fn id_algorithm(&self) -> HashAlgorithm {
+        [HashAlgorithm::Blake3, HashAlgorithm::Sha256]
+            .iter()
+            .copied()
+            .find(|algorithm| self.verify_id_with(algorithm))
+            .unwrap_or_default()
+    }
 
-impl Txns {
-    fn new() -> Self {
-        Self {
-            txns: Vec::new(),
-            merkle_root: [0; HASH_SIZE],
+    // Changing the fee changes the id, so any signature is dropped
+    fn with_fee(mut self, fee: u64) -> Self {
+        let algorithm = self.id_algorithm();
+        self.fee = fee;
+        self.id = Hash::default();
+        self.signature = Vec::new();
+        self.hash(&algorithm);
+        self
+    }
+
+    // Changing the expiry changes the id, so any signature is dropped
+    fn with_expiry(mut self, expiry: u64) -> Self {
+        let algorithm = self.id_algorithm();
+        self.expiry = Some(expiry);
+        self.id = Hash::default();
+        self.signature = Vec::new();
+        self.hash(&algorithm);
+        self
+    }
+
+    // Changing the metadata changes the id, so any signature is dropped
+    fn with_metadata(
+        mut self,
+        data: &[u8],
+    ) -> Result<Self, TransactionError> {
+        if data.len() > MAX_METADATA_SIZE {
+            return Err(
+                ValidationError::MetadataTooLarge(data.len()).into()
+            );
         }
+        let algorithm = self.id_algorithm();
+        self.metadata = Some(data.to_vec());
+        self.signature = Vec::new();
+        self.hash(&algorithm);
+        Ok(self)
     }
 
-    fn add(&mut self, txn: Txn) {
-        self.txns.push(txn);
+    fn metadata_len(&self) -> usize {
+        self.metadata.as_deref().map_or(0, <[u8]>::len)
     }
 
-    fn verify(&self) -> Valid {
-        Valid::Valid
-    } // Just verify all of them
+    fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expiry, Some(expiry) if expiry < now)
+    }
 
-    fn calc_merkle_root_r(leaves: &mut Vec<Hash>) -> Hash {
-        if leaves.len() == 1 {
-            return *leaves
-                .first()
-                .expect("Could not get last transaction");
+    // Refuse a transaction that is already on the chain. Pruned blocks
+    // are not searched.
+    fn double_spend_check(
+        &self,
+        chain: &Blockchain,
+    ) -> Result<(), TransactionError> {
+        match chain.find_transaction(&self.id) {
+            Some((block_index, _)) => {
+                Err(TransactionError::AlreadyIncluded { block_index })
+            }
+            None => Ok(()),
         }
+    }
 
-        if leaves.len() % 2 != 0 {
-            leaves.push(
-                *leaves.last().expect("Could not get last transaction"),
-            );
+    // The id is the hash of the transaction before it had an id or a
+    // signature
+    fn verify_id(&self) -> bool {
+        !self.id_is_stale()
+    }
+
+    fn verify_id_with(&self, algorithm: &HashAlgorithm) -> bool {
+        self.canonical_id_with(algorithm) == self.id
+    }
+
+    // What the id should be for the fields as they are now, which differs
+    // from `id` once a field has been changed without rehashing
+    fn canonical_id(&self) -> Hash {
+        self.canonical_id_with(&HashAlgorithm::default())
+    }
+
+    fn canonical_id_with(&self, algorithm: &HashAlgorithm) -> Hash {
+        let unhashed = Self {
+            id: Hash::default(),
+            ..self.unsigned()
+        };
+        algorithm.digest(&unhashed.to_bytes())
+    }
+
+    fn id_is_stale(&self) -> bool {
+        self.id_is_stale_with(&HashAlgorithm::default())
+    }
+
+    fn id_is_stale_with(&self, algorithm: &HashAlgorithm) -> bool {
+        self.id != self.canonical_id_with(algorithm)
+    }
+
+    // What a signature covers, in a fixed order that does not follow the
+    // struct. The id is the hash of every other field, so those are
+    // covered through it.
+    fn hash_for_signing(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.id.encode(&mut bytes);
+        self.sender.encode(&mut bytes);
+        self.recipient.encode(&mut bytes);
+        self.amount.encode(&mut bytes);
+        self.fee.encode(&mut bytes);
+        self.timestamp.encode(&mut bytes);
+        self.metadata.as_deref().encode(&mut bytes);
+        bytes
+    }
+
+    // A copy without any signatures
+    fn unsigned(&self) -> Self {
+        Self {
+            kind: self.kind.clone(),
+            metadata: self.metadata.clone(),
+            signature: Vec::new(),
+            ..*self
         }
+    }
 
-        let mut branches: Vec<Hash> = Vec::new();
+    fn is_signed(&self) -> bool {
+        !self.signature.is_empty()
+    }
 
-        for i in (0..leaves.len() - 1).step_by(2) {
-            let mut concat: [u8; HASH_SIZE * 2] = [0; HASH_SIZE * 2];
-            for j in 0..leaves[i].len() {
-                concat[j] = leaves[i][j];
-            }
+    fn is_coinbase(&self) -> bool {
+        matches!(self.kind, TxnKind::CoinbaseReward { .. })
+    }
+
+    fn to_hex_id(&self) -> String {
+        util::bytes_to_hex(&self.id.0)
+    }
+
+    // The bincode size
+    fn payload_size_bytes(&self) -> usize {
+        self.to_encoded().len()
+    }
+
+    // Moves nothing, so it is never valid in a block
+    fn is_self_transfer(&self) -> bool {
+        self.sender == self.recipient
+    }
+
+    fn is_multisig(&self) -> bool {
+        matches!(self.kind, TxnKind::MultiSig { .. })
+    }
+
+    // The number of signatures attached, for multi-sig transactions
+    fn multisig_count(&self) -> u8 {
+        (self.signature.len() / SIGNATURE_SIZE).min(u8::MAX as usize) as u8
+    }
+
+    // Needs the public key only. Multi-sig transactions are checked with
+    // verify_signatures instead, since they need every signatory's key.
+    // Prefer verify_and_recover_sender, which looks the keys up itself.
+    fn verify(&self, key: PublicKey) -> Result<(), ValidationError> {
+        self.verify_one(&self.signature, key)
+    }
+
+    fn verify_one(
+        &self,
+        signature: &[u8],
+        key: PublicKey,
+    ) -> Result<(), ValidationError> {
+        let signature = Signature::from_bytes(signature)
+            .map_err(|_| ValidationError::InvalidSignature)?;
+        key.verify::<Sha512>(&self.hash_for_signing(), &signature)
+            .map_err(|_| ValidationError::InvalidSignature)
+    }
+
+    // Check the signatures against the registry: the sender's for a
+    // transfer, or enough of the declared signatories' for a multi-sig.
+    // Coinbase rewards are never signed.
+    fn verify_signatures(
+        &self,
+        key_registry: &HashMap<Address, PublicKey>,
+    ) -> Result<(), ValidationError> {
+        match &self.kind {
+            TxnKind::Transfer => match key_registry.get(&self.sender) {
+                Some(key) => self.verify(*key),
+                None => Err(ValidationError::UnknownSender(self.sender)),
+            },
+            TxnKind::CoinbaseReward { .. } => Ok(()),
+            TxnKind::MultiSig {
+                required,
+                signatories,
+            } => {
+                // Each signatory counts once, however often they signed
+                let valid = signatories
+                    .iter()
+                    .filter_map(|signatory| key_registry.get(signatory))
+                    .filter(|key| {
+                        self.signature
+                            .chunks(SIGNATURE_SIZE)
+                            .any(|sig| self.verify_one(sig, **key).is_ok())
+                    })
+                    .count()
+                    .min(u8::MAX as usize)
+                    as u8;
 
-            for j in 0..leaves[i + 1].len() {
-                concat[j + HASH_SIZE] = leaves[i][j];
+                if *required > 0 && valid >= *required {
+                    Ok(())
+                } else {
+                    Err(ValidationError::InsufficientSignatures {
+                        required: *required,
+                        valid,
+                    })
+                }
             }
-            branches.push(*blake3::hash(&concat).as_bytes());
         }
-        Txns::calc_merkle_root_r(&mut branches)
     }
 
-    fn calc_merkle_root(&mut self) {
-        let mut merkle_leaves: Vec<Hash> =
-            (&self.txns).into_iter().map(|txn| txn.id).collect();
-        self.merkle_root = Txns::calc_merkle_root_r(&mut merkle_leaves);
+    // Check the signatures against the registry and hand back who sent
+    // it: the signer of a transfer, or the shared address of a multi-sig.
+    // Coinbase rewards have no sender to recover.
+    fn verify_and_recover_sender(
+        &self,
+        key_registry: &HashMap<Address, PublicKey>,
+    ) -> Result<Address, TransactionError> {
+        if self.is_coinbase() {
+            return Err(TransactionError::Coinbase);
+        }
+        self.verify_signatures(key_registry)?;
+        Ok(self.sender)
+    }
+
+    // Needs the private key
+    fn sign(&mut self, key: &Keypair) {
+        let signature = key.sign::<Sha512>(&self.hash_for_signing());
+        self.signature = signature.to_bytes().to_vec(); // Set the signature
+    }
+
+    // Sign a multi-sig transaction on behalf of one more signatory. Other
+    // transactions take a single signature.
+    fn add_signature(
+        &mut self,
+        key: &Keypair,
+    ) -> Result<(), TransactionError> {
+        let already_signed = self
+            .signature
+            .chunks(SIGNATURE_SIZE)
+            .any(|sig| self.verify_one(sig, key.public).is_ok());
+        if already_signed || (self.is_signed() && !self.is_multisig()) {
+            return Err(TransactionError::AlreadySigned);
+        }
+
+        let signature = key.sign::<Sha512>(&self.hash_for_signing());
+        self.signature.extend_from_slice(&signature.to_bytes());
+        Ok(())
     }
 }
 
 trait Hashable {
-    fn hash(&mut self);
+    fn hash(&mut self, algorithm: &HashAlgorithm);
 }
 
 impl Hashable for Block {
-    fn hash(&mut self) {
-        let bytes =
-            &bincode::serialize(self).expect("Could not serialize block");
-        self.hash = *blake3::hash(bytes).as_bytes();
+    fn hash(&mut self, algorithm: &HashAlgorithm) {
+        self.hash = self.calc_hash(algorithm);
+        self.address_index.take();
     }
 }
 
+// Hashes the transaction as it was before it had an id or a signature,
+// so it can be rehashed at any point
 impl Hashable for Txn {
-    fn hash(&mut self) {
-        let bytes = &self.to_bytes();
-        self.id = *blake3::hash(bytes).as_bytes();
+    fn hash(&mut self, algorithm: &HashAlgorithm) {
+        self.id = self.canonical_id_with(algorithm);
     }
 }
 
+// Only the public key goes into the address, so anyone holding the key
+// can work out where to send funds
 impl Hashable for User {
-    fn hash(&mut self) {
-        let bytes =
-            &bincode::serialize(self).expect("Could not serialize user");
-        self.address = *blake3::hash(bytes).as_bytes();
+    fn hash(&mut self, algorithm: &HashAlgorithm) {
+        self.address =
+            Address::from_public_key_with(&self.public_key, algorithm);
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
 struct Block {
     hash: Hash,
     prev_hash: Hash,
     txns: Txns,
     index: u32,
     timestamp: u128,
-    nonce: f64,
+    nonce: u64,
+    // Every sender and recipient, built on the first contains_address
+    // call. Not serialized, and dropped whenever the block is rehashed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    address_index: OnceLock<HashSet<Address>>,
 }
 
+// The address index is derived from the transactions, so it takes no
+// part in equality
+impl PartialEq for Block {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && self.prev_hash == other.prev_hash
+            && self.txns == other.txns
+            && self.index == other.index
+            && self.timestamp == other.timestamp
+            && self.nonce == other.nonce
+    }
+}
+
+impl Eq for Block {}
+
 impl Block {
     fn new(prev_hash: Hash, txns: Txns, index: u32) -> Self {
+        Self::new_with_proof_of_work(prev_hash, txns, index, 0)
+    }
+
+    fn new_with_proof_of_work(
+        prev_hash: Hash,
+        txns: Txns,
+        index: u32,
+        difficulty: u8,
+    ) -> Self {
+        Self::new_with_hash_algorithm(
+            prev_hash,
+            txns,
+            index,
+            difficulty,
+            &HashAlgorithm::default(),
+        )
+    }
+
+    // Count the nonce up from zero until the hash starts with
+    // `difficulty` zero bytes
+    fn new_with_hash_algorithm(
+        prev_hash: Hash,
+        txns: Txns,
+        index: u32,
+        difficulty: u8,
+        algorithm: &HashAlgorithm,
+    ) -> Self {
         let mut block = Self {
-            hash: [0; HASH_SIZE],
+            hash: Hash::default(),
             prev_hash,
             txns,
             index,
-            nonce: gen_nonce(),
-            timestamp: Instant::now().elapsed().as_millis(),
+            nonce: 0,
+            timestamp: now_millis(),
+            address_index: OnceLock::new(),
         };
-        block.hash();
+        block.hash(algorithm);
+        while !block.meets_difficulty(difficulty) {
+            block.nonce += 1;
+            block.hash(algorithm);
+        }
         block
     }
-}
 
-#[derive(Serialize, Deserialize)]
-struct Blockchain {
-    blocks: Vec<Block>,
-    timestamp: u128,
-}
+    fn meets_difficulty(&self, difficulty: u8) -> bool {
+        let difficulty = difficulty as usize;
+        difficulty <= HASH_SIZE
+            && self.hash.0[..difficulty].iter().all(|&b| b == 0)
+    }
 
-impl Blockchain {
-    fn new() -> Self {
-        Self {
-            blocks: Vec::new(),
-            timestamp: Instant::now().elapsed().as_millis(),
+    // Check everything that can be checked without the rest of the chain
+    fn validate(&self) -> Result<(), ValidationError> {
+        self.validate_with(&HashAlgorithm::default())
+    }
+
+    fn validate_with(
+        &self,
+        algorithm: &HashAlgorithm,
+    ) -> Result<(), ValidationError> {
+        if !self.verify_hash(algorithm) {
+            return Err(ValidationError::HashMismatch);
+        }
+
+        if self.txns.compute_merkle_root_with(algorithm)
+            != self.txns.merkle_root
+        {
+            return Err(ValidationError::MerkleRootMismatch);
+        }
+
+        if let Some(txn) =
+            self.txns.iter().find(|txn| !txn.verify_id_with(algorithm))
+        {
+            return Err(ValidationError::StaleTransactionId(txn.id));
+        }
+
+        if let Some(id) = self.txns.first_duplicate() {
+            return Err(ValidationError::DuplicateTransaction {
+                id,
+                block_index: self.index,
+            });
+        }
+
+        // Metadata can be built past the limit by hand, not just through
+        // with_metadata
+        if let Some(txn) = self
+            .txns
+            .iter()
+            .find(|txn| txn.metadata_len() > MAX_METADATA_SIZE)
+        {
+            return Err(ValidationError::MetadataTooLarge(
+                txn.metadata_len(),
+            ));
+        }
+
+        if !self.txns.all_signed() {
+            return Err(ValidationError::UnsignedTransaction);
+        }
+
+        // Only the genesis block may point at the zero hash
+        if (self.index == 0) != (self.prev_hash == Hash::default()) {
+            return Err(ValidationError::GenesisMismatch(self.index));
         }
+
+        if self.timestamp == 0
+            || self.timestamp > now_millis() + MAX_FUTURE_DRIFT_MILLIS
+        {
+            return Err(ValidationError::InvalidTimestamp(self.timestamp));
+        }
+
+        self.txns.verify()
     }
 
-    fn add_block(&mut self, block: Block) {
-        self.blocks.push(block);
+    fn verify_hash(&self, algorithm: &HashAlgorithm) -> bool {
+        self.calc_hash(algorithm) == self.hash
     }
 
-    fn verify(&self) -> Valid {
-        Valid::Valid
+    fn transaction_count(&self) -> usize {
+        self.txns.len()
     }
-}
 
-fn main() {
-    // Make some users
-    let user1 = User::from_uid("new_user");
-    let user1_privkey = User::get_keypair("new_user");
-    let user2 = User::new("user2");
+    // Whether `addr` sends or receives anything in this block. The first
+    // call indexes every address in the block, so later calls are a
+    // lookup. Coinbase rewards have no sender to index.
+    fn contains_address(&self, addr: &Address) -> bool {
+        self.address_index
+            .get_or_init(|| {
+                self.txns
+                    .iter()
+                    .flat_map(|txn| {
+                        let sender = Some(txn.sender)
+                            .filter(|_| !txn.is_coinbase());
+                        sender.into_iter().chain(Some(txn.recipient))
+                    })
+                    .collect()
+            })
+            .contains(addr)
+    }
 
-    // Make some txns
-    let mut txns1 = Txns::new();
-    for amount in vec![10.0, 11.0, 12.0] {
-        let mut txn = Txn::new(&user1, &user2, amount);
-        txn.sign(&user1_privkey);
-        txns1.add(txn);
+    // A linear scan of the ids, O(n) in the transactions but without any
+    // hashing
+    fn contains_transaction(&self, txn_id: &Hash) -> bool {
+        self.txns.iter().any(|txn| txn.id == *txn_id)
     }
-    txns1.calc_merkle_root(); // Calc the merkle root hash
-    assert!(match txns1.verify() {
-        Valid::Valid => true,
-        Valid::Invalid => false,
-    }); // Verify the txns
 
-    // Make some more txns
-    let mut txns2 = Txns::new();
-    for amount in vec![20.0, 21.0, 22.0] {
-        txns2.add(Txn::new(&user1, &user2, amount));
+    // A proof against this block's merkle root, labelled with the block.
+    // Rebuilds the tree, so O(n) hashes in the transactions.
+    fn merkle_proof_for(&self, txn_id: &Hash) -> Option<MerkleProof> {
+        self.merkle_proof_for_with(txn_id, &HashAlgorithm::default())
     }
-    txns2.calc_merkle_root(); // Calc the merkle root hash
-    assert!(match txns2.verify() {
-        Valid::Valid => true,
-        Valid::Invalid => false,
-    }); // Verify the txns
 
-    // Make some blocks
-    let block1 = Block::new([0; HASH_SIZE], txns1, 0);
-    println!("Made a new block! {:?}", block1);
+    fn merkle_proof_for_with(
+        &self,
+        txn_id: &Hash,
+        algorithm: &HashAlgorithm,
+    ) -> Option<MerkleProof> {
+        let proof = self.txns.generate_proof_with(txn_id, algorithm)?;
+        Some(MerkleProof {
+            block: Some((self.hash, self.index)),
+            ..proof
+        })
+    }
 
-    let block2 = Block::new(block1.hash, txns2, 1);
-    println!("Made a new block! {:?}", block2);
+    fn to_hex_hash(&self) -> String {
+        util::bytes_to_hex(&self.hash.0)
+    }
 
-    let mut blockchain = Blockchain::new();
-    blockchain.add_block(block1);
-    blockchain.add_block(block2);
+    // The bincode size
+    fn size_bytes(&self) -> usize {
+        self.to_encoded().len()
+    }
 
-    /* ----- VALIDATION ----- */
-    let t_txn = &blockchain.blocks[0].txns.txns[0];
-    println!(
-        "txn 0 in block 0 is {}",
-        match t_txn.verify(user1.public_key) {
-            Valid::Valid => "valid!",
-            Valid::Invalid => "invalid!",
+    // The bincode bytes, with or without the serde feature
+    fn as_bytes(&self) -> Vec<u8> {
+        self.to_encoded()
+    }
+
+    // Decode a block written by as_bytes. Nothing is checked, since the
+    // hashes depend on the chain's hash algorithm; validate_with, or
+    // adding it to a chain, does that.
+    #[cfg(feature = "serde")]
+    fn from_bytes(bytes: &[u8]) -> Result<Self, BlockchainError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    // Sums every fee on each call, nothing is cached
+    fn total_fees(&self) -> u64 {
+        self.txns.iter().map(|txn| txn.fee).sum()
+    }
+
+    // The coins its coinbase reward creates
+    fn minted(&self) -> u64 {
+        self.txns
+            .iter()
+            .filter(|txn| txn.is_coinbase())
+            .fold(0, |total, txn| total.saturating_add(txn.amount))
+    }
+
+    // The hash covers the header, and so the transactions through their
+    // merkle root, which validation checks against them
+    fn calc_hash(&self, algorithm: &HashAlgorithm) -> Hash {
+        self.as_header().calc_hash(algorithm)
+    }
+
+    fn as_header(&self) -> BlockHeader {
+        BlockHeader {
+            hash: self.hash,
+            prev_hash: self.prev_hash,
+            merkle_root: self.txns.merkle_root,
+            index: self.index,
+            timestamp: self.timestamp,
+            nonce: self.nonce,
         }
-    );
+    }
+}
+
+// What a pruned chain remembers about the blocks it dropped: enough to
+// carry on verifying and replaying balances from the first kept block
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PrunedState {
+    last_hash: Hash, // Of the last pruned block
+    balances: HashMap<Address, u64>,
+    difficulty: u8,
+    last_retarget_index: u32,
+    last_retarget_timestamp: u128,
+}
+
+// Enough of a chain to bootstrap a node without replaying it: the
+// balances and the tip to build on
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BlockchainSnapshot {
+    tip_hash: Hash,
+    tip_index: u32,
+    utxo_set: HashMap<Address, u64>,
+    timestamp: u128, // Of the tip block
+}
+
+// Summary figures over the stored blocks, for diagnostics
+#[derive(Debug, Clone, PartialEq)]
+struct ChainStats {
+    block_count: u32,
+    transaction_count: usize,
+    total_transferred: u64, // Excludes newly minted coinbase rewards
+    unique_addresses: usize,
+    oldest_block_timestamp: u128,
+    newest_block_timestamp: u128,
+    average_block_time_ms: f64, // 0 with fewer than two blocks
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Blockchain {
+    blocks: Vec<Block>, // Genesis, then everything from pruned_from on
+    timestamp: u128,
+    initial_difficulty: u8,
+    target_difficulty: u8, // Leading zero bytes required of the next block
+    last_retarget_index: u32,
+    retarget_interval: u32,
+    hash_algorithm: HashAlgorithm, // For blocks, txn ids and merkle roots
+    pruned_from: u32,              // 0 if nothing has been pruned
+    pruned_state: PrunedState,
+    orphan_ttl: u32, // Blocks an orphan is kept for while it waits
+    #[cfg_attr(feature = "serde", serde(skip))]
+    orphan_blocks: Vec<(Block, u32)>, // With the height when each arrived
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hash_index: HashMap<Hash, usize>, // Block hash -> position in blocks
+    #[cfg_attr(feature = "serde", serde(skip))]
+    utxo_cache: Option<HashMap<Address, u64>>, // Chain plus applied txns
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_verified: u32, // Index of the last block known to be valid
+    #[cfg_attr(feature = "serde", serde(skip))]
+    checkpoints: Vec<(u32, Hash)>, // Confirmed by checkpoint, by index
+}
+
+// The hash index, balance cache and last verified index are derived from
+// the blocks, orphans are not part of the chain yet and checkpoints are
+// what this node trusts, so they take no part in equality
+impl PartialEq for Blockchain {
+    fn eq(&self, other: &Self) -> bool {
+        self.blocks == other.blocks
+            && self.timestamp == other.timestamp
+            && self.initial_difficulty == other.initial_difficulty
+            && self.target_difficulty == other.target_difficulty
+            && self.last_retarget_index == other.last_retarget_index
+            && self.retarget_interval == other.retarget_interval
+            && self.hash_algorithm == other.hash_algorithm
+            && self.pruned_from == other.pruned_from
+            && self.pruned_state == other.pruned_state
+            && self.orphan_ttl == other.orphan_ttl
+    }
+}
+
+impl Eq for Blockchain {}
+
+impl Blockchain {
+    fn new() -> Self {
+        Self::with_retargeting(0, DEFAULT_RETARGET_INTERVAL)
+    }
+
+    // Blocks after genesis start at the initial difficulty, which is then
+    // retargeted every `retarget_interval` blocks
+    fn with_retargeting(
+        initial_difficulty: u8,
+        retarget_interval: u32,
+    ) -> Self {
+        Self {
+            blocks: Vec::new(),
+            timestamp: now_millis(),
+            initial_difficulty,
+            target_difficulty: initial_difficulty,
+            last_retarget_index: 0,
+            retarget_interval,
+            hash_algorithm: HashAlgorithm::default(),
+            pruned_from: 0,
+            pruned_state: PrunedState::default(),
+            orphan_ttl: DEFAULT_ORPHAN_TTL,
+            orphan_blocks: Vec::new(),
+            hash_index: HashMap::new(),
+            utxo_cache: None,
+            last_verified: 0,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    // An empty chain whose blocks must be hashed with `hash_algorithm`
+    fn with_hash_algorithm(hash_algorithm: HashAlgorithm) -> Self {
+        Self {
+            hash_algorithm,
+            ..Self::new()
+        }
+    }
+
+    // A chain whose first block mints the genesis reward to the recipient
+    fn with_genesis(coinbase_recipient: &User) -> Self {
+        let mut txns = Txns::new();
+        txns.add(Txn::new_coinbase(coinbase_recipient, GENESIS_REWARD, 0))
+            .expect("Coinbase reward is not too large");
+        txns.calc_merkle_root();
+
+        let mut blockchain = Self::new();
+        blockchain
+            .add_block(Block::new(Hash::default(), txns, 0))
+            .expect("Genesis block does not extend an empty chain");
+        blockchain
+    }
+
+    // A chain of blocks from elsewhere, such as a peer, verified as a
+    // whole before it is handed back. Uses the default difficulty schedule.
+    fn from_blocks(blocks: Vec<Block>) -> Result<Self, BlockchainError> {
+        let mut blockchain = Self {
+            blocks,
+            ..Self::new()
+        };
+        blockchain.reindex();
+        blockchain.verify()?;
+        blockchain.last_verified = blockchain.height();
+
+        let (difficulty, last_retarget_index) =
+            blockchain.replay_difficulty(blockchain.blocks.len())?;
+        blockchain.target_difficulty = difficulty;
+        blockchain.last_retarget_index = last_retarget_index;
+        Ok(blockchain)
+    }
+
+    // The newest block; None for an empty chain, or a snapshot chain that
+    // has nothing past the snapshot yet
+    fn tip(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    // The first block, unless the chain was restored from a snapshot
+    fn genesis(&self) -> Option<&Block> {
+        self.blocks.first().filter(|block| block.index == 0)
+    }
+
+    // True if no blocks are stored, even if some were pruned
+    fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    // Index of the tip block; also 0 for an empty chain
+    fn height(&self) -> u32 {
+        self.tip()
+            .map_or(self.pruned_from.saturating_sub(1), |block| {
+                block.index
+            })
+    }
+
+    // The block must extend the tip: the next index, pointing at the tip
+    // hash. The first block must have index 0 and a zero prev hash, or
+    // follow the snapshot the chain was restored from. It must also be
+    // valid on its own and not repeat any transaction already mined.
+    fn validate_block_before_adding(
+        &self,
+        block: &Block,
+    ) -> Result<(), BlockchainError> {
+        let (expected_index, expected_prev) = self.next_link();
+        if block.index != expected_index {
+            return Err(ValidationError::UnexpectedIndex {
+                expected: expected_index,
+                got: block.index,
+            }
+            .into());
+        }
+        if block.prev_hash != expected_prev {
+            return Err(ValidationError::BrokenChain {
+                expected: expected_prev,
+                got: block.prev_hash,
+            }
+            .into());
+        }
+        block.validate_with(&self.hash_algorithm)?;
+        // The same proof of work verify asks of every block after genesis
+        if block.index > 0
+            && !block.meets_difficulty(self.target_difficulty)
+        {
+            return Err(ValidationError::InsufficientWork {
+                index: block.index,
+                difficulty: self.target_difficulty,
+            }
+            .into());
+        }
+        let supply = self.total_supply().saturating_add(block.minted());
+        if supply > MAX_SUPPLY {
+            return Err(ValidationError::SupplyCapExceeded {
+                block_index: block.index,
+                supply,
+            }
+            .into());
+        }
+        let size = block.size_bytes();
+        if size > MAX_BLOCK_SIZE {
+            return Err(ValidationError::BlockTooLarge {
+                block_index: block.index,
+                size,
+            }
+            .into());
+        }
+        for txn in block.txns.iter() {
+            if let Err(TransactionError::AlreadyIncluded { block_index }) =
+                txn.double_spend_check(self)
+            {
+                return Err(ValidationError::DuplicateTransaction {
+                    id: txn.id,
+                    block_index,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    // The index and prev hash the next block must have
+    fn next_link(&self) -> (u32, Hash) {
+        match self.tip() {
+            Some(tip) => (self.height() + 1, tip.hash),
+            None if self.is_pruned() => {
+                (self.pruned_from, self.pruned_state.last_hash)
+            }
+            None => (0, Hash::default()),
+        }
+    }
+
+    // Add a run of blocks, such as one received from a peer. The whole
+    // run must link up to the tip before any of it is added; after that
+    // blocks are added in order until one fails, and the error says how
+    // many made it. Returns the number of blocks added.
+    fn apply_blocks(
+        &mut self,
+        blocks: impl Iterator<Item = Block>,
+    ) -> Result<u32, BlockchainError> {
+        let blocks: Vec<Block> = blocks.collect();
+        let mut link = self.next_link();
+        for block in &blocks {
+            let (expected_index, expected_prev) = link;
+            let error = if block.index != expected_index {
+                ValidationError::UnexpectedIndex {
+                    expected: expected_index,
+                    got: block.index,
+                }
+            } else if block.prev_hash != expected_prev {
+                ValidationError::BrokenChain {
+                    expected: expected_prev,
+                    got: block.prev_hash,
+                }
+            } else {
+                link = (block.index + 1, block.hash);
+                continue;
+            };
+            return Err(BlockchainError::BatchFailed {
+                applied: 0,
+                error: Box::new(error.into()),
+            });
+        }
+
+        let mut applied = 0;
+        for block in blocks {
+            if let Err(e) = self.add_block(block) {
+                return Err(BlockchainError::BatchFailed {
+                    applied,
+                    error: Box::new(e),
+                });
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    // Add a block to the tip, then any orphans that now follow on from it
+    fn add_block(&mut self, block: Block) -> Result<(), BlockchainError> {
+        self.append_block(block)?;
+        self.try_adopt_orphans();
+        Ok(())
+    }
+
+    // Hold on to a block whose parent has not arrived yet. It is added as
+    // soon as it follows on from the tip, or dropped after `orphan_ttl`
+    // more blocks.
+    fn add_orphan(&mut self, block: Block) {
+        if self
+            .orphan_blocks
+            .iter()
+            .all(|(held, _)| held.hash != block.hash)
+        {
+            self.orphan_blocks.push((block, self.height()));
+        }
+        self.try_adopt_orphans();
+    }
+
+    fn orphan_count(&self) -> usize {
+        self.orphan_blocks.len()
+    }
+
+    // Add every orphan that follows on from the tip, one after another.
+    // An orphan that fails validation is dropped.
+    fn try_adopt_orphans(&mut self) {
+        let (height, ttl) = (self.height(), self.orphan_ttl);
+        self.orphan_blocks
+            .retain(|(_, arrived)| height.saturating_sub(*arrived) <= ttl);
+
+        loop {
+            let (_, expected_prev) = self.next_link();
+            let position = self
+                .orphan_blocks
+                .iter()
+                .position(|(block, _)| block.prev_hash == expected_prev);
+            match position {
+                Some(position) => {
+                    let (orphan, _) = self.orphan_blocks.remove(position);
+                    let _ = self.append_block(orphan);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn append_block(
+        &mut self,
+        block: Block,
+    ) -> Result<(), BlockchainError> {
+        self.validate_block_before_adding(&block)?;
+        let (difficulty, last_retarget_index) = self.retarget(
+            self.target_difficulty,
+            self.last_retarget_index,
+            &block,
+        )?;
+        let index = block.index;
+
+        self.hash_index.insert(block.hash, self.blocks.len());
+        self.blocks.push(block);
+        self.utxo_cache = None; // Applied txns may now be in the block
+        self.last_verified = index;
+        self.target_difficulty = difficulty;
+        self.last_retarget_index = last_retarget_index;
+        Ok(())
+    }
+
+    fn current_difficulty(&self) -> u8 {
+        self.target_difficulty
+    }
+
+    // The difficulty and last retarget index once `tip` is on the chain.
+    // Blocks that came faster than the target interval raise the
+    // difficulty by one; slower blocks lower it by one, down to 1. The
+    // last retarget index may come from a file, so it is checked.
+    fn retarget(
+        &self,
+        difficulty: u8,
+        last_retarget_index: u32,
+        tip: &Block,
+    ) -> Result<(u8, u32), ValidationError> {
+        let since = tip.index.checked_sub(last_retarget_index).ok_or(
+            ValidationError::InvalidRetargetIndex {
+                last_retarget_index,
+                tip: tip.index,
+            },
+        )?;
+        if since < self.retarget_interval {
+            return Ok((difficulty, last_retarget_index));
+        }
+
+        let first = match self.get_block_by_index(last_retarget_index) {
+            Some(block) => block.timestamp,
+            None => self.pruned_state.last_retarget_timestamp,
+        };
+        let elapsed = tip.timestamp.saturating_sub(first);
+        let expected = since as u128 * TARGET_BLOCK_MILLIS;
+
+        let difficulty = if elapsed < expected {
+            difficulty.saturating_add(1).min(HASH_SIZE as u8)
+        } else if elapsed > expected && difficulty > 1 {
+            difficulty - 1
+        } else {
+            difficulty
+        };
+        Ok((difficulty, tip.index))
+    }
+
+    // Rebuild the hash index, which is not serialized
+    fn reindex(&mut self) {
+        self.hash_index = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.hash, i))
+            .collect();
+    }
+
+    // Write to a temp file in the same directory, then rename it into
+    // place, so an existing chain file is never left half-overwritten
+    #[cfg(feature = "serde")]
+    fn to_disk(&self, path: &Path) -> Result<(), BlockchainError> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut payload = CHAIN_SCHEMA_VERSION.to_le_bytes().to_vec();
+        bincode::serialize_into(&mut payload, self)?;
+
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(&storage::seal(&payload))?;
+        f.sync_all()?;
+
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    // Load a chain and verify it before handing it back. Files written
+    // with an older schema version are migrated first.
+    #[cfg(feature = "serde")]
+    fn from_disk(path: &Path) -> Result<Self, BlockchainError> {
+        let mut f = File::open(path)?;
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer)?;
+
+        let payload = storage::unseal(&buffer)?;
+        if payload.len() < 4 {
+            return Err(StorageError::CorruptedFile.into());
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&payload[..4]);
+        let (version, payload) =
+            (u32::from_le_bytes(version), &payload[4..]);
+        let mut blockchain: Self = if version == CHAIN_SCHEMA_VERSION {
+            bincode::deserialize(payload)?
+        } else {
+            bincode::deserialize(&migration::migrate_chain(
+                payload, version,
+            )?)?
+        };
+        blockchain.reindex();
+        blockchain.verify()?;
+        blockchain.last_verified = blockchain.height();
+        Ok(blockchain)
+    }
+
+    // Load a chain file written before files were sealed, which is bare
+    // bincode in the first schema without a version, verify it and write
+    // it back in the current format. Sealed files load as with from_disk.
+    #[cfg(feature = "serde")]
+    fn migrate_legacy_file(path: &Path) -> Result<Self, BlockchainError> {
+        let buffer = fs::read(path)?;
+        if storage::is_sealed(&buffer) {
+            return Blockchain::from_disk(path);
+        }
+        let mut blockchain: Self =
+            bincode::deserialize(&migration::migrate_chain(&buffer, 1)?)?;
+        blockchain.reindex();
+        blockchain.verify()?;
+        blockchain.last_verified = blockchain.height();
+        blockchain.to_disk(path)?;
+        Ok(blockchain)
+    }
+
+    // Stream the chain as plain bincode, without the file wrapper or a
+    // full copy in memory
+    #[cfg(feature = "serde")]
+    fn serialize_to_writer(
+        &self,
+        writer: impl Write,
+    ) -> Result<(), BlockchainError> {
+        Ok(bincode::serialize_into(writer, self)?)
+    }
+
+    // Read a chain written by serialize_to_writer and verify it
+    #[cfg(feature = "serde")]
+    fn deserialize_from_reader(
+        reader: impl Read,
+    ) -> Result<Self, BlockchainError> {
+        let mut blockchain: Self = bincode::deserialize_from(reader)?;
+        blockchain.reindex();
+        blockchain.verify()?;
+        blockchain.last_verified = blockchain.height();
+        Ok(blockchain)
+    }
+
+    // A fresh copy of the chain written and read back in the current
+    // schema, with every transaction read back through Txn::from_bytes.
+    // The upgrade path once a chain file has been migrated. Orphans,
+    // caches and checkpoints are left behind, and the copy is verified
+    // before it is handed back.
+    #[cfg(feature = "serde")]
+    fn compact(&self) -> Result<Blockchain, BlockchainError> {
+        let mut compacted: Self =
+            bincode::deserialize(&bincode::serialize(self)?)?;
+        for block in compacted.blocks.iter_mut() {
+            for txn in block.txns.iter_mut() {
+                *txn = Txn::from_bytes(&txn.to_bytes())?;
+            }
+        }
+        compacted.reindex();
+        compacted.verify()?;
+        compacted.last_verified = compacted.height();
+        Ok(compacted)
+    }
+
+    fn get_block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.hash_index.get(hash).map(|&i| &self.blocks[i])
+    }
+
+    // How many blocks are stored ahead of the pruned gap: genesis, which
+    // pruning keeps, or nothing for a chain restored from a snapshot
+    fn offset(&self) -> usize {
+        usize::from(self.genesis().is_some())
+    }
+
+    // Where the block with this index would be stored, given that
+    // pruning drops the blocks between genesis and pruned_from
+    fn position(&self, index: u32) -> Option<usize> {
+        if index == 0 || self.pruned_from == 0 {
+            Some(index as usize)
+        } else if index < self.pruned_from {
+            None
+        } else {
+            Some((index - self.pruned_from) as usize + self.offset())
+        }
+    }
+
+    // The index of the block stored at this position
+    fn index_at(&self, position: usize) -> u32 {
+        if self.pruned_from == 0 || position < self.offset() {
+            position as u32
+        } else {
+            self.pruned_from + (position - self.offset()) as u32
+        }
+    }
+
+    // None if there is no such block, or if the block stored at that
+    // position does not have the requested index
+    fn get_block_by_index(&self, index: u32) -> Option<&Block> {
+        self.blocks
+            .get(self.position(index)?)
+            .filter(|block| block.index == index)
+    }
+
+    // The latest block mined at or before `ts`, or None if `ts` is before
+    // genesis. Assumes timestamps increase along the chain.
+    fn block_at_timestamp(&self, ts: u128) -> Option<&Block> {
+        let after =
+            self.blocks.partition_point(|block| block.timestamp <= ts);
+        after.checked_sub(1).map(|i| &self.blocks[i])
+    }
+
+    // Blocks mined from `start_ms` through `end_ms_inclusive`, oldest
+    // first. Same assumption as block_at_timestamp.
+    fn find_blocks_by_time_range(
+        &self,
+        start_ms: u128,
+        end_ms_inclusive: u128,
+    ) -> Vec<&Block> {
+        let first = self
+            .blocks
+            .partition_point(|block| block.timestamp < start_ms);
+        self.blocks[first..]
+            .iter()
+            .take_while(|block| block.timestamp <= end_ms_inclusive)
+            .collect()
+    }
+
+    // The transactions in those blocks, in chain order
+    fn find_transactions_by_time_range(
+        &self,
+        start_ms: u128,
+        end_ms_inclusive: u128,
+    ) -> Vec<&Txn> {
+        self.find_blocks_by_time_range(start_ms, end_ms_inclusive)
+            .into_iter()
+            .flat_map(|block| block.txns.iter())
+            .collect()
+    }
+
+    // The difficulty and last retarget index that verification starts
+    // from, just after genesis or just after the pruned blocks
+    fn replay_start(&self) -> (u8, u32) {
+        if self.is_pruned() {
+            (
+                self.pruned_state.difficulty,
+                self.pruned_state.last_retarget_index,
+            )
+        } else {
+            (self.initial_difficulty, 0)
+        }
+    }
+
+    // Replay the difficulty schedule over the stored blocks after
+    // genesis, up to but not including the given position
+    fn replay_difficulty(
+        &self,
+        end: usize,
+    ) -> Result<(u8, u32), ValidationError> {
+        self.blocks[..end]
+            .iter()
+            .filter(|block| block.index > 0)
+            .try_fold(
+                self.replay_start(),
+                |(difficulty, last_retarget_index), block| {
+                    self.retarget(difficulty, last_retarget_index, block)
+                },
+            )
+    }
+
+    // (block index, difficulty it had to meet) for every stored block
+    // whose difficulty differs from the block before, starting with the
+    // initial difficulty at index 0. Replays the schedule in one pass,
+    // stopping early on a chain whose schedule does not verify.
+    fn difficulty_history(&self) -> Vec<(u32, u8)> {
+        let mut history = vec![(0, self.initial_difficulty)];
+        let (mut difficulty, mut last_retarget_index) =
+            self.replay_start();
+        for block in self.blocks.iter().filter(|block| block.index > 0) {
+            if history.last().map(|&(_, last)| last) != Some(difficulty) {
+                history.push((block.index, difficulty));
+            }
+            match self.retarget(difficulty, last_retarget_index, block) {
+                Ok((next, retargeted_at)) => {
+                    difficulty = next;
+                    last_retarget_index = retargeted_at;
+                }
+                Err(_) => break,
+            }
+        }
+        history
+    }
+
+    // A copy of the chain up to and including the given index
+    fn fork_at(&self, index: u32) -> Option<Blockchain> {
+        if self.is_empty() || index > self.height() {
+            return None;
+        }
+        let mut fork = self.clone();
+        fork.blocks.truncate(self.position(index)? + 1);
+        fork.reindex();
+        fork.utxo_cache = None;
+        fork.last_verified = fork.last_verified.min(index);
+
+        let (difficulty, last_retarget_index) =
+            fork.replay_difficulty(fork.blocks.len()).ok()?;
+        fork.target_difficulty = difficulty;
+        fork.last_retarget_index = last_retarget_index;
+        Some(fork)
+    }
+
+    // Remove the last `n` blocks, returning them oldest first. Genesis
+    // stays, as does the first block kept by pruning, since the chain
+    // could not be rebuilt past them.
+    fn rollback(&mut self, n: u32) -> Result<Vec<Block>, BlockchainError> {
+        let keep = if self.is_pruned() {
+            self.offset() + 1
+        } else {
+            1
+        };
+        let removable = self.blocks.len().saturating_sub(keep);
+        if n as usize > removable {
+            return Err(BlockchainError::CannotRollbackGenesis);
+        }
+        let keep = self.blocks.len() - n as usize;
+        let (difficulty, last_retarget_index) =
+            self.replay_difficulty(keep)?;
+        let removed = self.blocks.split_off(keep);
+        self.reindex();
+        self.utxo_cache = None;
+        self.last_verified = self.last_verified.min(self.height());
+        self.target_difficulty = difficulty;
+        self.last_retarget_index = last_retarget_index;
+        Ok(removed)
+    }
+
+    // Rehash every transaction, merkle root and block with the chain's
+    // hash algorithm, relinking each block to the one before it, after a
+    // migration that changed what the hashes cover. New ids invalidate
+    // every signature and new block hashes almost never meet the
+    // difficulty, so this is only for development and data migration.
+    // Nothing is verified afterwards.
+    fn recompute_all_hashes(&mut self) {
+        let algorithm = self.hash_algorithm;
+        let first_kept = Some(self.offset()).filter(|_| self.is_pruned());
+        let mut prev_hash = Hash::default();
+        for (i, block) in self.blocks.iter_mut().enumerate() {
+            // The first kept block follows the last pruned one, which
+            // cannot be rehashed
+            if Some(i) == first_kept {
+                prev_hash = self.pruned_state.last_hash;
+            }
+            for txn in block.txns.iter_mut() {
+                txn.hash(&algorithm);
+            }
+            block.txns.calc_merkle_root_with(&algorithm);
+            block.prev_hash = prev_hash;
+            block.hash(&algorithm);
+            prev_hash = block.hash;
+        }
+        self.reindex();
+        self.utxo_cache = None;
+        self.last_verified = 0;
+    }
+
+    // Hash every block again, relinking each to the one before it and
+    // mining it again at the difficulty it had to meet, after a migration
+    // that changed what block hashes cover. Each block must first hash to
+    // its stored hash under `old_hash`, link to the block before and meet
+    // its difficulty, so only a chain that verified under the old rules
+    // comes out. The transactions are left as they are, so their ids and
+    // signatures still hold, and the schedule only depends on indexes and
+    // timestamps, so it replays unchanged.
+    #[cfg(feature = "serde")]
+    fn rehash_blocks(
+        &mut self,
+        old_hash: fn(&Block, &HashAlgorithm) -> Hash,
+    ) -> Result<(), MigrationError> {
+        let algorithm = self.hash_algorithm;
+        let first_kept = Some(self.offset()).filter(|_| self.is_pruned());
+        let (mut difficulty, mut last_retarget_index) =
+            self.replay_start();
+        let (mut old_prev_hash, mut prev_hash) =
+            (Hash::default(), Hash::default());
+        for i in 0..self.blocks.len() {
+            if Some(i) == first_kept {
+                old_prev_hash = self.pruned_state.last_hash;
+                prev_hash = self.pruned_state.last_hash;
+            }
+            let required = difficulty;
+            let block = &self.blocks[i];
+            if block.prev_hash != old_prev_hash
+                || block.hash != old_hash(block, &algorithm)
+                || (block.index > 0 && !block.meets_difficulty(required))
+            {
+                return Err(MigrationError::InvalidBlock(block.index));
+            }
+            if block.index > 0 {
+                (difficulty, last_retarget_index) =
+                    self.retarget(difficulty, last_retarget_index, block)?;
+            }
+            old_prev_hash = block.hash;
+
+            let block = &mut self.blocks[i];
+            block.prev_hash = prev_hash;
+            block.hash(&algorithm);
+            let mut attempts = 1;
+            while block.index > 0 && !block.meets_difficulty(required) {
+                if attempts == MAX_REMINE_ATTEMPTS {
+                    return Err(MigrationError::MiningLimit(block.index));
+                }
+                attempts += 1;
+                block.nonce = block.nonce.wrapping_add(1);
+                block.hash(&algorithm);
+            }
+            prev_hash = block.hash;
+        }
+        self.reindex();
+        self.utxo_cache = None;
+        self.last_verified = 0;
+        Ok(())
+    }
+
+    fn is_pruned(&self) -> bool {
+        self.pruned_from > 0
+    }
+
+    fn snapshot(&self) -> Result<BlockchainSnapshot, ValidationError> {
+        let (tip_hash, timestamp) = match self.tip() {
+            Some(tip) => (tip.hash, tip.timestamp),
+            None => (
+                self.pruned_state.last_hash,
+                self.pruned_state.last_retarget_timestamp,
+            ),
+        };
+        Ok(BlockchainSnapshot {
+            tip_hash,
+            tip_index: self.height(),
+            utxo_set: self.compute_utxo_set()?,
+            timestamp,
+        })
+    }
+
+    // A chain with every block pruned, ready for the block after the
+    // snapshot tip. Difficulty starts over from the default schedule,
+    // retargeting from the tip.
+    fn from_snapshot(snap: BlockchainSnapshot) -> Self {
+        let chain = Self::new();
+        Self {
+            pruned_from: snap.tip_index + 1,
+            pruned_state: PrunedState {
+                last_hash: snap.tip_hash,
+                balances: snap.utxo_set,
+                difficulty: chain.initial_difficulty,
+                last_retarget_index: snap.tip_index,
+                last_retarget_timestamp: snap.timestamp,
+            },
+            last_retarget_index: snap.tip_index,
+            ..chain
+        }
+    }
+
+    // Drop the blocks between genesis and `keep_from_index`, handing them
+    // back for archiving. Genesis is always kept. The balances and
+    // difficulty as of the last dropped block are kept so the rest of
+    // the chain can still be verified and replayed.
+    fn prune(
+        &mut self,
+        keep_from_index: u32,
+    ) -> Result<Vec<Block>, BlockchainError> {
+        if self.is_empty() || keep_from_index > self.height() {
+            return Err(BlockchainError::PruneOutOfRange {
+                keep_from: keep_from_index,
+                height: self.height(),
+            });
+        }
+        let end = match self.position(keep_from_index) {
+            Some(end) if end > self.offset() => end,
+            _ => return Ok(Vec::new()), // Already pruned that far
+        };
+
+        let (difficulty, last_retarget_index) =
+            self.replay_difficulty(end)?;
+        let last_retarget_timestamp =
+            match self.get_block_by_index(last_retarget_index) {
+                Some(block) => block.timestamp,
+                None => self.pruned_state.last_retarget_timestamp,
+            };
+        self.pruned_state = PrunedState {
+            last_hash: self.blocks[end - 1].hash,
+            balances: self.balances_through(end)?,
+            difficulty,
+            last_retarget_index,
+            last_retarget_timestamp,
+        };
+        self.pruned_from = keep_from_index;
+
+        let pruned = self.blocks.drain(self.offset()..end).collect();
+        self.reindex();
+        self.utxo_cache = None;
+        Ok(pruned)
+    }
+
+    fn is_longer_than(&self, other: &Blockchain) -> bool {
+        // Heights rather than lengths, as pruned chains store fewer blocks
+        let has_blocks =
+            |chain: &Blockchain| !chain.is_empty() || chain.is_pruned();
+        has_blocks(self)
+            && (!has_blocks(other) || self.height() > other.height())
+    }
+
+    // The fork choice rule: the higher chain, or on equal heights the one
+    // with more proof of work, or else `a`. Both should already be
+    // verified.
+    fn longest_chain<'a>(
+        a: &'a Blockchain,
+        b: &'a Blockchain,
+    ) -> &'a Blockchain {
+        if a.is_longer_than(b) {
+            a
+        } else if b.is_longer_than(a)
+            || b.cumulative_work() > a.cumulative_work()
+        {
+            b
+        } else {
+            a
+        }
+    }
+
+    // Leading zero bits summed over the stored block hashes. Pruned
+    // blocks are not counted.
+    fn cumulative_work(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|block| u64::from(block.hash.leading_zero_bits()))
+            .sum()
+    }
+
+    // The highest index at which both chains hold the same block
+    fn common_ancestor(&self, other: &Blockchain) -> Option<u32> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|block| other.get_block_by_hash(&block.hash).is_some())
+            .map(|block| block.index)
+    }
+
+    // Switch to a longer, valid chain that forks from this one. Returns
+    // the blocks that are no longer on the chain, so their transactions
+    // can go back to the mempool.
+    fn reorg(
+        &mut self,
+        new_chain: Blockchain,
+    ) -> Result<Vec<Block>, ReorgError> {
+        new_chain.verify()?;
+        if !new_chain.is_longer_than(self) {
+            return Err(ReorgError::ShorterChain);
+        }
+
+        let keep = if self.is_empty() {
+            0
+        } else {
+            let ancestor = self
+                .common_ancestor(&new_chain)
+                .ok_or(ReorgError::NoCommonAncestor)?;
+            self.position(ancestor)
+                .ok_or(ReorgError::NoCommonAncestor)?
+                + 1
+        };
+        let orphaned = self.blocks.split_off(keep);
+        *self = new_chain;
+        self.last_verified = self.height();
+        Ok(orphaned)
+    }
+
+    // The stored blocks without their transactions, for light clients
+    fn headers(&self) -> Vec<BlockHeader> {
+        self.blocks.iter().map(Block::as_header).collect()
+    }
+
+    fn from_headers(headers: Vec<BlockHeader>) -> HeaderChain {
+        HeaderChain::new(headers)
+    }
+
+    // Every transaction in chain order, with the index of its block
+    fn iter_transactions(&self) -> impl Iterator<Item = (u32, &Txn)> {
+        self.blocks.iter().flat_map(|block| {
+            block.txns.iter().map(move |txn| (block.index, txn))
+        })
+    }
+
+    fn walk(&self) -> ChainWalker<'_> {
+        ChainWalker::new(&self.blocks)
+    }
+
+    // Walks the whole chain on each call
+    fn statistics(&self) -> ChainStats {
+        let mut addresses = HashSet::new();
+        let mut total_transferred = 0;
+        for (_, txn) in self.walk() {
+            if !txn.is_coinbase() {
+                addresses.insert(txn.sender);
+                total_transferred += txn.amount;
+            }
+            addresses.insert(txn.recipient);
+        }
+
+        let timestamp =
+            |block: Option<&Block>| block.map_or(0, |b| b.timestamp);
+        let deltas: Vec<u128> = self
+            .blocks
+            .windows(2)
+            .map(|pair| {
+                pair[1].timestamp.saturating_sub(pair[0].timestamp)
+            })
+            .collect();
+        let average_block_time_ms = if deltas.is_empty() {
+            0.0
+        } else {
+            deltas.iter().sum::<u128>() as f64 / deltas.len() as f64
+        };
+
+        ChainStats {
+            block_count: self.blocks.len() as u32,
+            transaction_count: self.total_transaction_count(),
+            total_transferred,
+            unique_addresses: addresses.len(),
+            oldest_block_timestamp: timestamp(self.blocks.first()),
+            newest_block_timestamp: timestamp(self.tip()),
+            average_block_time_ms,
+        }
+    }
+
+    fn find_transaction(&self, id: &Hash) -> Option<(u32, &Txn)> {
+        self.iter_transactions().find(|(_, txn)| txn.id == *id)
+    }
+
+    // For ids typed in by hand; fails only if the id is not valid hex
+    fn find_transaction_by_hex(
+        &self,
+        hex_id: &str,
+    ) -> Result<Option<(u32, &Txn)>, HexError> {
+        Ok(self.find_transaction(&Hash::from_hex(hex_id)?))
+    }
+
+    // Both walk every stored block on each call, nothing is cached.
+    // Pruned blocks are not counted.
+    fn total_transaction_count(&self) -> usize {
+        self.blocks.iter().map(Block::transaction_count).sum()
+    }
+
+    fn total_fees(&self) -> u64 {
+        self.blocks.iter().map(Block::total_fees).sum()
+    }
+
+    // Every coin minted by a coinbase reward. Pruned blocks count as the
+    // balances they left, so fees burned before pruning are not included.
+    fn total_supply(&self) -> u64 {
+        let (pruned, start) = self.supply_start();
+        self.blocks[start..]
+            .iter()
+            .map(Block::minted)
+            .fold(pruned, u64::saturating_add)
+    }
+
+    // The supply before the first stored block that is not already part
+    // of the pruned balances, and that block's position
+    fn supply_start(&self) -> (u64, usize) {
+        if self.is_pruned() {
+            let pruned = self
+                .pruned_state
+                .balances
+                .values()
+                .fold(0, |total: u64, &balance| {
+                    total.saturating_add(balance)
+                });
+            (pruned, self.offset())
+        } else {
+            (0, 0)
+        }
+    }
+
+    // How many transactions `addr` has sent, or received. Each call is
+    // O(n) over every stored transaction, so callers checking many
+    // addresses on a long chain should count once and cache the result.
+    // Pruned blocks are not counted.
+    fn transaction_count_by_address(&self, addr: &Address) -> usize {
+        self.walk().filter(|(_, txn)| txn.sender == *addr).count()
+    }
+
+    fn received_count_by_address(&self, addr: &Address) -> usize {
+        self.walk()
+            .filter(|(_, txn)| txn.recipient == *addr)
+            .count()
+    }
+
+    // The timestamp of the first, or last, block in which `addr` sends or
+    // receives anything. Stops at the first match from that end. Pruned
+    // blocks are not searched.
+    fn address_first_seen(&self, addr: &Address) -> Option<u128> {
+        self.blocks
+            .iter()
+            .find(|block| block.contains_address(addr))
+            .map(|block| block.timestamp)
+    }
+
+    fn address_last_seen(&self, addr: &Address) -> Option<u128> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|block| block.contains_address(addr))
+            .map(|block| block.timestamp)
+    }
+
+    // Replay every transaction from genesis, or from the pruned balances.
+    // Coinbase rewards mint new coins; transfers move the amount and burn
+    // the fee from the sender, who must be able to cover both at that
+    // point in history.
+    fn compute_utxo_set(
+        &self,
+    ) -> Result<HashMap<Address, u64>, ValidationError> {
+        self.balances_through(self.blocks.len())
+    }
+
+    // Balances after the stored blocks up to but not including the given
+    // position. Genesis is already part of the pruned balances.
+    fn balances_through(
+        &self,
+        end: usize,
+    ) -> Result<HashMap<Address, u64>, ValidationError> {
+        let (mut balances, start) = if self.is_pruned() {
+            (self.pruned_state.balances.clone(), self.offset())
+        } else {
+            (HashMap::new(), 0)
+        };
+        for block in &self.blocks[start.min(end)..end] {
+            for txn in block.txns.iter() {
+                Blockchain::apply_to_balances(&mut balances, txn)?;
+            }
+        }
+        Ok(balances)
+    }
+
+    fn apply_to_balances(
+        balances: &mut HashMap<Address, u64>,
+        txn: &Txn,
+    ) -> Result<(), ValidationError> {
+        if !txn.is_coinbase() {
+            let available =
+                balances.get(&txn.sender).copied().unwrap_or(0);
+            let requested = txn.amount.saturating_add(txn.fee);
+            if requested > available {
+                return Err(ValidationError::InsufficientFunds {
+                    sender: txn.sender,
+                    available,
+                    requested,
+                });
+            }
+            balances.insert(txn.sender, available - requested);
+        }
+        *balances.entry(txn.recipient).or_insert(0) += txn.amount;
+        Ok(())
+    }
+
+    // Check a signed transfer against the chain plus every transaction
+    // applied so far, then apply it. The cache is rebuilt from the chain
+    // whenever a block is added.
+    fn apply_transaction(
+        &mut self,
+        txn: &Txn,
+        sender_key: &PublicKey,
+    ) -> Result<(), TransactionError> {
+        if txn.is_coinbase() {
+            return Err(TransactionError::Coinbase);
+        }
+        if !txn.verify_id_with(&self.hash_algorithm) {
+            return Err(TransactionError::InvalidHash);
+        }
+        txn.verify(*sender_key)
+            .map_err(|_| TransactionError::InvalidSignature)?;
+
+        // A rejected transaction leaves the balances untouched
+        let mut balances = match self.utxo_cache.take() {
+            Some(balances) => balances,
+            None => self.compute_utxo_set()?,
+        };
+        let result = Blockchain::apply_to_balances(&mut balances, txn);
+        self.utxo_cache = Some(balances);
+        result.map_err(TransactionError::Invalid)
+    }
+
+    // None if the address has never transacted, or if the chain
+    // overspends somewhere
+    fn balance(&self, addr: &Address) -> Option<u64> {
+        self.compute_utxo_set().ok()?.get(addr).copied()
+    }
+
+    // Verify the chain structure only, without checking signatures
+    fn verify(&self) -> Result<(), ValidationError> {
+        self.verify_with_keys(&HashMap::new())
+    }
+
+    // Verify the chain structure, plus the signature of every signed
+    // transaction whose sender is in the key store
+    fn verify_with_keys(
+        &self,
+        key_store: &HashMap<Address, PublicKey>,
+    ) -> Result<(), ValidationError> {
+        self.verify_from(0, key_store)
+    }
+
+    // Confirm that the block at `index` has a known hash, such as one
+    // shipped with the software, so verify_incremental can trust the
+    // blocks before it
+    fn checkpoint(
+        &mut self,
+        index: u32,
+        expected_hash: Hash,
+    ) -> Result<(), CheckpointError> {
+        let got = self
+            .get_block_by_index(index)
+            .ok_or(CheckpointError::MissingBlock(index))?
+            .hash;
+        if got != expected_hash {
+            return Err(CheckpointError::HashMismatch {
+                index,
+                expected: expected_hash,
+                got,
+            });
+        }
+        if !self.checkpoints.contains(&(index, expected_hash)) {
+            self.checkpoints.push((index, expected_hash));
+        }
+        Ok(())
+    }
+
+    // The highest checkpoint whose block is still on the chain, so one
+    // left behind by a rollback or reorg is no longer trusted
+    fn highest_checkpoint(&self) -> Option<u32> {
+        self.checkpoints
+            .iter()
+            .filter(|(index, hash)| {
+                self.get_block_by_index(*index)
+                    .is_some_and(|block| block.hash == *hash)
+            })
+            .map(|(index, _)| *index)
+            .max()
+    }
+
+    // Verify the blocks from `from_index` on, trusting the ones before it,
+    // such as everything up to `last_verified`, or before the highest
+    // checkpoint if that is later. The first of them must still follow on
+    // from the block before it. An index in the pruned gap starts from the
+    // first kept block.
+    fn verify_incremental(
+        &self,
+        from_index: u32,
+    ) -> Result<(), ValidationError> {
+        let from_index = self
+            .highest_checkpoint()
+            .map_or(from_index, |checkpoint| checkpoint.max(from_index));
+        let start = self.position(from_index).unwrap_or(self.offset());
+        self.verify_from(start.min(self.blocks.len()), &HashMap::new())
+    }
+
+    // Verify the stored blocks from the given position on, picking up the
+    // difficulty schedule and supply where the blocks before it left them
+    fn verify_from(
+        &self,
+        start: usize,
+        key_store: &HashMap<Address, PublicKey>,
+    ) -> Result<(), ValidationError> {
+        let mut prev_hash = start
+            .checked_sub(1)
+            .map_or(Hash::default(), |i| self.blocks[i].hash);
+        // The pruned blocks were last retargeted at or before the last of
+        // them
+        if self.is_pruned()
+            && self.pruned_state.last_retarget_index >= self.pruned_from
+        {
+            return Err(ValidationError::InvalidRetargetIndex {
+                last_retarget_index: self.pruned_state.last_retarget_index,
+                tip: self.pruned_from - 1,
+            });
+        }
+        let (mut difficulty, mut last_retarget_index) =
+            self.replay_difficulty(start)?;
+        let (mut supply, supply_from) = self.supply_start();
+        for block in &self.blocks[supply_from.min(start)..start] {
+            supply = supply.saturating_add(block.minted());
+        }
+        // Where each transaction was first included, to catch replays
+        let mut included: HashMap<Hash, u32> = HashMap::new();
+        for block in &self.blocks[..start] {
+            for txn in block.txns.iter() {
+                included.entry(txn.id).or_insert(block.index);
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate().skip(start) {
+            if block.index != self.index_at(i) {
+                return Err(ValidationError::UnexpectedIndex {
+                    expected: self.index_at(i),
+                    got: block.index,
+                });
+            }
+
+            // The first kept block follows the last pruned one
+            if self.is_pruned() && i == self.offset() {
+                prev_hash = self.pruned_state.last_hash;
+            }
+
+            if block.prev_hash != prev_hash {
+                return Err(ValidationError::BrokenChain {
+                    expected: prev_hash,
+                    got: block.prev_hash,
+                });
+            }
+
+            block.validate_with(&self.hash_algorithm)?;
+
+            let size = block.size_bytes();
+            if size > MAX_BLOCK_SIZE {
+                return Err(ValidationError::BlockTooLarge {
+                    block_index: block.index,
+                    size,
+                });
+            }
+
+            if i >= supply_from {
+                supply = supply.saturating_add(block.minted());
+                if supply > MAX_SUPPLY {
+                    return Err(ValidationError::SupplyCapExceeded {
+                        block_index: block.index,
+                        supply,
+                    });
+                }
+            }
+
+            // Replay the difficulty schedule up to this block
+            if block.index > 0 {
+                if !block.meets_difficulty(difficulty) {
+                    return Err(ValidationError::InsufficientWork {
+                        index: block.index,
+                        difficulty,
+                    });
+                }
+                let (next, retargeted_at) =
+                    self.retarget(difficulty, last_retarget_index, block)?;
+                difficulty = next;
+                last_retarget_index = retargeted_at;
+            }
+
+            for txn in block.txns.iter() {
+                if !txn.verify_id_with(&self.hash_algorithm) {
+                    return Err(ValidationError::HashMismatch);
+                }
+                if let Some(&block_index) = included.get(&txn.id) {
+                    return Err(ValidationError::DuplicateTransaction {
+                        id: txn.id,
+                        block_index,
+                    });
+                }
+                included.insert(txn.id, block.index);
+                if txn.is_expired(block.timestamp as u64) {
+                    return Err(ValidationError::ExpiredTransaction(
+                        txn.id,
+                    ));
+                }
+                if txn.is_multisig() {
+                    txn.verify_signatures(key_store)?;
+                    continue;
+                }
+                if !txn.is_signed() {
+                    continue;
+                }
+                if let Some(key) = key_store.get(&txn.sender) {
+                    txn.verify(*key)?;
+                }
+            }
+
+            prev_hash = block.hash;
+        }
+        Ok(())
+    }
+}
+
+// Without JSON there is no chain file to load or write back, so build a
+// chain for two throwaway users and check it. Their keys go to a private
+// temporary directory, removed when the demo ends.
+#[cfg(not(feature = "serde_json"))]
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = StorageConfig::new(dir.path(), dir.path());
+    let user1 = User::new("throwaway_user1", &cfg).unwrap();
+    let user1_privkey =
+        User::get_keypair("throwaway_user1", &cfg).unwrap();
+    let user2 = User::new("throwaway_user2", &cfg).unwrap();
+
+    let mut blockchain = Blockchain::with_genesis(&user1);
+    let mut txns = Txns::new();
+    txns.add(Txn::new_signed(&user1, &user1_privkey, &user2, COIN))
+        .unwrap();
+    txns.calc_merkle_root();
+    let block = Block::new(blockchain.genesis().unwrap().hash, txns, 1);
+    blockchain.add_block(block).unwrap();
+
+    println!("{}", blockchain);
+    println!(
+        "{} has {}, {} has {}",
+        user1.uid,
+        display_amount(user1.balance(&blockchain)),
+        user2.uid,
+        display_amount(user2.balance(&blockchain))
+    );
+}
+
+#[cfg(feature = "serde_json")]
+fn main() {
+    // Make some users, in a temporary directory so the demo never
+    // touches stored keys
+    let dir = tempfile::tempdir().unwrap();
+    let cfg = StorageConfig::new(dir.path(), dir.path());
+    let user1 = User::new("new_user", &cfg).unwrap();
+    let user1_privkey = User::get_keypair("new_user", &cfg).unwrap();
+    let user2 = User::new("user2", &cfg).unwrap();
+
+    // Make some txns
+    let mut txns1 = Txns::new();
+    for amount in [10, 11, 12] {
+        txns1
+            .add(Txn::new_signed(
+                &user1,
+                &user1_privkey,
+                &user2,
+                amount * COIN,
+            ))
+            .unwrap();
+    }
+    txns1.calc_merkle_root(); // Calc the merkle root hash
+
+    // Make some more txns, and sign them all at once
+    let mut txns2 = Txns::new();
+    for amount in [20, 21, 22] {
+        txns2.add(Txn::new(&user1, &user2, amount * COIN)).unwrap();
+    }
+    txns2.batch_sign(&user1_privkey);
+    txns2.calc_merkle_root();
+
+    // Make some blocks
+    let block1 = Block::new(Hash::default(), txns1, 0);
+    println!("Made a new block! {}", block1);
+
+    let block2 = Block::new(block1.hash, txns2, 1);
+    println!("Made a new block! {}", block2);
+
+    let mut blockchain = Blockchain::new();
+    blockchain.add_block(block1).unwrap();
+    blockchain.add_block(block2).unwrap();
+
+    /* ----- VALIDATION ----- */
+    let t_txn = blockchain.blocks[0].txns.iter().next().unwrap();
+    println!(
+        "txn 0 in block 0 is {}",
+        match t_txn.verify(user1.public_key) {
+            Ok(()) => String::from("valid!"),
+            Err(e) => format!("invalid! ({})", e),
+        }
+    );
+    println!("{}", blockchain.to_json().unwrap());
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use mempool::MemPool;
+    use tempfile::TempDir;
+    use txns::TxnsBuilder;
+    use zeroize::Zeroize;
+
+    // A user with a fresh keypair, kept in memory
+    pub(crate) fn keyed_user(uid: &str) -> (User, Keypair) {
+        let mut csprng = OsRng::new().unwrap();
+        let keypair = Keypair::generate::<Sha512, _>(&mut csprng);
+        let mut user = User {
+            address: Address::default(),
+            timestamp: now_millis(),
+            nonce: gen_nonce().unwrap(),
+            public_key: keypair.public,
+            uid: String::from(uid),
+        };
+        user.hash(&HashAlgorithm::default());
+        (user, keypair)
+    }
+
+    // Data and secret directories of the test's own, removed when the
+    // TempDir is dropped
+    pub(crate) fn temp_cfg() -> (TempDir, StorageConfig) {
+        let dir = TempDir::new().unwrap();
+        let cfg = StorageConfig::new(dir.path(), dir.path());
+        (dir, cfg)
+    }
+
+    fn transfers(
+        user1: &User,
+        key1: &Keypair,
+        user2: &User,
+        amounts: &[u64],
+    ) -> Txns {
+        let mut txns = Txns::new();
+        for amount in amounts {
+            txns.add(Txn::new_signed(user1, key1, user2, amount * COIN))
+                .unwrap();
+        }
+        txns.calc_merkle_root();
+        txns
+    }
+
+    // Two blocks of transfers from user1 to user2. user1 was never
+    // credited, so the chain overspends.
+    pub(crate) fn transfer_chain(
+        user1: &User,
+        key1: &Keypair,
+        user2: &User,
+    ) -> Blockchain {
+        let block1 = Block::new(
+            Hash::default(),
+            transfers(user1, key1, user2, &[10, 11, 12]),
+            0,
+        );
+        let block2 = Block::new(
+            block1.hash,
+            transfers(user1, key1, user2, &[20, 21, 22]),
+            1,
+        );
+        let mut chain = Blockchain::new();
+        chain.add_block(block1).unwrap();
+        chain.add_block(block2).unwrap();
+        chain
+    }
+
+    // A genesis block for user1, then four blocks each paying user2 a
+    // coinbase and a transfer from user1
+    pub(crate) fn walked_chain(
+        user1: &User,
+        key1: &Keypair,
+        user2: &User,
+    ) -> Blockchain {
+        let mut walked = Blockchain::with_genesis(user1);
+        for index in 1..5 {
+            let txns = TxnsBuilder::new()
+                .add_coinbase(user2, GENESIS_REWARD, index)
+                .add_transfer(user1, key1, user2, COIN * index as u64, 1)
+                .build()
+                .unwrap();
+            let prev_hash = walked.tip().unwrap().hash;
+            walked
+                .add_block(Block::new(prev_hash, txns, index))
+                .unwrap();
+        }
+        walked
+    }
+
+    // The chain as a version 1 payload, with every block hashed over its
+    // transactions and mined at the difficulty it had to meet
+    #[cfg(feature = "serde")]
+    pub(crate) fn to_v1_bytes(blockchain: &Blockchain) -> Vec<u8> {
+        let mut old = blockchain.clone();
+        let algorithm = old.hash_algorithm;
+        let mut prev_hash = Hash::default();
+        for i in 0..old.blocks.len() {
+            let (required, _) = blockchain.replay_difficulty(i).unwrap();
+            let block = &mut old.blocks[i];
+            block.prev_hash = prev_hash;
+            block.hash = migration::v1_block_hash(block, &algorithm);
+            while block.index > 0 && !block.meets_difficulty(required) {
+                block.nonce += 1;
+                block.hash = migration::v1_block_hash(block, &algorithm);
+            }
+            prev_hash = block.hash;
+        }
+        bincode::serialize(&old).unwrap()
+    }
+
+    // Three blocks mined faster than the target interval, which raises
+    // the difficulty to 2
+    pub(crate) fn fast_chain() -> Blockchain {
+        let mut fast_chain = Blockchain::with_retargeting(0, 1);
+        fast_chain
+            .add_block(Block::new(Hash::default(), Txns::new(), 0))
+            .unwrap();
+        for index in 1..3 {
+            let block = Block::new_with_proof_of_work(
+                fast_chain.tip().unwrap().hash,
+                Txns::new(),
+                index,
+                fast_chain.current_difficulty(),
+            );
+            fast_chain.add_block(block).unwrap();
+        }
+        fast_chain
+    }
+
+    #[test]
+    fn existing_users_are_only_replaced_on_request() {
+        let (_dir, cfg) = temp_cfg();
+        let user1 = User::overwrite("new_user", &cfg).unwrap();
+        let user2 = User::overwrite("user2", &cfg).unwrap();
+        assert!(user1.verify_address() && user2.verify_address());
+
+        assert!(User::uid_exists("new_user", &cfg));
+        assert!(User::uid_exists("user2", &cfg));
+        assert!(!User::uid_exists("nobody", &cfg));
+        assert!(!User::uid_exists("../new_user", &cfg));
+        assert!(matches!(
+            User::new("new_user", &cfg),
+            Err(BlockchainError::User(UserError::AlreadyExists(uid)))
+                if uid == "new_user"
+        ));
+        assert!(matches!(
+            User::create_new("user2", &cfg),
+            Err(BlockchainError::User(UserError::AlreadyExists(_)))
+        ));
+        assert_eq!(
+            User::get_keypair("user2", &cfg).unwrap().public,
+            user2.public_key
+        );
+        assert!(matches!(
+            User::get_keypair("nobody", &cfg),
+            Err(BlockchainError::KeyNotFound(_))
+        ));
+        std::fs::write(cfg.secret_dir.join("user2.priv"), b"corrupted")
+            .unwrap();
+        assert!(matches!(
+            User::get_keypair("user2", &cfg),
+            Err(BlockchainError::InvalidKeyFile { uid, .. }) if uid == "user2"
+        ));
+    }
+
+    #[test]
+    fn uids_that_could_escape_are_refused() {
+        let (_dir, cfg) = temp_cfg();
+        for uid in
+            ["../../etc/passwd", "a/b", "", "dot.ted", &"x".repeat(65)]
+        {
+            assert!(matches!(
+                validate_uid(uid),
+                Err(UserError::InvalidUid(_))
+            ));
+            assert!(matches!(
+                User::new(uid, &cfg),
+                Err(BlockchainError::User(UserError::InvalidUid(_)))
+            ));
+            #[cfg(feature = "serde")]
+            assert!(User::from_uid(uid, &cfg).is_err());
+            assert!(!KeyStore::new(&cfg.secret_dir).contains(uid));
+        }
+        assert!(validate_uid("Some_user-64").is_ok());
+    }
+
+    #[test]
+    fn users_known_by_public_key_are_receive_only() {
+        let (_dir, cfg) = temp_cfg();
+        let (user1, _) = keyed_user("user1");
+        let user2 = User::overwrite("user2", &cfg).unwrap();
+        let remote = User::from_public_key(user2.public_key);
+        assert_eq!(remote.address, user2.address);
+        assert!(remote.verify_address());
+        assert_eq!(remote.uid, user2.address.to_string());
+        assert!(validate_uid(&remote.uid).is_ok());
+        assert_eq!((remote.timestamp, remote.nonce), (0, 0));
+        assert!(remote.is_receive_only(&cfg));
+        assert!(!user2.is_receive_only(&cfg));
+        assert_eq!(
+            Txn::new(&user1, &remote, COIN).recipient,
+            user2.address
+        );
+    }
+
+    #[test]
+    fn secret_keys_can_be_wiped() {
+        let (_dir, cfg) = temp_cfg();
+        let user = User::overwrite("new_user", &cfg).unwrap();
+        let key = User::get_keypair("new_user", &cfg).unwrap();
+        let mut wiped = User::get_keypair("new_user", &cfg).unwrap();
+        assert_eq!(wiped.public, key.public);
+        assert_eq!(wiped.public, user.public_key);
+        wiped.zeroize();
+        assert!(wiped.secret.as_bytes().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rotating_a_key_replaces_the_stored_key() {
+        let (_dir, cfg) = temp_cfg();
+        let user2 = User::overwrite("user2", &cfg).unwrap();
+        let store = KeyStore::new(&cfg.secret_dir);
+        let (rotated, new_key) = user2.rotate_keypair(&store).unwrap();
+        assert_eq!(rotated.uid, user2.uid);
+        assert_ne!(rotated.address, user2.address);
+        assert!(rotated.verify_address());
+        assert_eq!(rotated.public_key, new_key.public);
+        assert_eq!(
+            store.get("user2").unwrap().unwrap().public,
+            new_key.public
+        );
+        let missing_dir =
+            KeyStore::new(cfg.secret_dir.join("no such directory"));
+        assert!(matches!(
+            user2.rotate_keypair(&missing_dir),
+            Err(UserError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn users_round_trip_through_bytes() {
+        let (user2, _) = keyed_user("user2");
+        let (other, _) = keyed_user("other");
+
+        // Only the public key counts towards the address
+        let bytes = user2.to_bytes().unwrap();
+        assert_eq!(User::from_bytes(&bytes).unwrap(), user2);
+        assert_eq!(
+            Address::from_public_key(&user2.public_key),
+            user2.address
+        );
+        let mut renamed = user2.clone();
+        renamed.uid.push('_');
+        renamed.nonce += 1;
+        assert_eq!(
+            User::from_bytes(&renamed.to_bytes().unwrap())
+                .unwrap()
+                .address,
+            user2.address
+        );
+        let mut rekeyed = user2.clone();
+        rekeyed.public_key = other.public_key;
+        assert!(matches!(
+            User::from_bytes(&rekeyed.to_bytes().unwrap()),
+            Err(UserError::InvalidAddress)
+        ));
+        let mut legacy = user2.clone();
+        legacy.address = legacy.legacy_address();
+        assert_eq!(
+            User::from_bytes(&legacy.to_bytes().unwrap()).unwrap(),
+            user2
+        );
+        assert!(User::from_bytes(&bytes[1..]).is_err());
+        assert_eq!(
+            user2.to_encoded(),
+            bincode::serialize(&user2).unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn users_live_in_the_configured_directory() {
+        let (_dir, cfg) = temp_cfg();
+        let (user2, _) = keyed_user("user2");
+        user2.to_disk(&cfg).unwrap();
+        let loaded = User::from_uid("user2", &cfg).unwrap();
+        assert_eq!(loaded.nonce, user2.nonce);
+        assert_eq!(loaded.address, user2.address);
+
+        let mut escaped = user2.clone();
+        escaped.uid = String::from("../escaped");
+        assert!(matches!(
+            escaped.to_disk(&cfg),
+            Err(BlockchainError::User(UserError::InvalidUid(_)))
+        ));
+    }
+
+    #[test]
+    fn self_transfers_are_refused() {
+        let (user1, key1) = keyed_user("user1");
+        let mut looped = Txns::new();
+        looped
+            .add(Txn::new_signed(&user1, &key1, &user1, COIN))
+            .unwrap();
+        looped.calc_merkle_root();
+        assert!(looped.iter().all(Txn::is_self_transfer));
+        assert!(matches!(
+            Block::new(Hash::default(), looped, 0).validate(),
+            Err(ValidationError::SelfTransfer(_))
+        ));
+    }
+
+    #[test]
+    fn blocks_only_take_signed_transactions() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut unsigned = Txns::new();
+        for amount in [20, 21, 22] {
+            unsigned
+                .add(Txn::new(&user1, &user2, amount * COIN))
+                .unwrap();
+        }
+        unsigned.calc_merkle_root();
+        assert!(matches!(
+            Block::new(Hash::default(), unsigned.clone(), 0).validate(),
+            Err(ValidationError::UnsignedTransaction)
+        ));
+        unsigned.batch_sign(&key1);
+        assert!(Block::new(Hash::default(), unsigned, 0)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn blocks_validate_their_position_and_time() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        assert!(chain.blocks.iter().all(|block| block.validate().is_ok()));
+
+        let orphan = Block::new(Hash::default(), Txns::new(), 1);
+        assert!(matches!(
+            orphan.validate(),
+            Err(ValidationError::GenesisMismatch(1))
+        ));
+        let mut future = Block::new(chain.blocks[1].hash, Txns::new(), 2);
+        future.timestamp += 2 * MAX_FUTURE_DRIFT_MILLIS;
+        future.hash(&HashAlgorithm::Blake3);
+        assert!(matches!(
+            future.validate(),
+            Err(ValidationError::InvalidTimestamp(_))
+        ));
+    }
+
+    #[test]
+    fn blocks_prove_their_own_transactions() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let (block1, block2) = (&chain.blocks[0], &chain.blocks[1]);
+        for txn in block2.txns.iter() {
+            assert!(block2.contains_transaction(&txn.id));
+            let proof = block2.merkle_proof_for(&txn.id).unwrap();
+            assert_eq!(proof.block, Some((block2.hash, 1)));
+            assert!(proof.verify(&txn.id, &block2.txns.merkle_root));
+        }
+        let first_id = block1.txns.iter().next().unwrap().id;
+        assert!(!block2.contains_transaction(&first_id));
+        assert!(block2.merkle_proof_for(&first_id).is_none());
+    }
+
+    #[test]
+    fn blocks_index_their_addresses_on_first_use() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let block =
+            transfer_chain(&user1, &key1, &user2).blocks[1].clone();
+        let unindexed = block.clone();
+        assert!(block.contains_address(&user1.address));
+        assert!(block.contains_address(&user2.address));
+        assert!(!block.contains_address(&Address::default()));
+        assert_eq!(block, unindexed);
+
+        let mut rescanned = block.clone();
+        rescanned.txns = TxnsBuilder::new()
+            .add_coinbase(&user2, GENESIS_REWARD, 1)
+            .build()
+            .unwrap();
+        rescanned.hash(&HashAlgorithm::Blake3);
+        assert!(!rescanned.contains_address(&user1.address));
+        assert!(rescanned.contains_address(&user2.address));
+        assert!(!rescanned.contains_address(&Address::default()));
+    }
+
+    #[test]
+    fn chains_count_transactions_fees_and_supply() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let empty = Blockchain::new();
+        assert!(empty.is_empty() && empty.tip().is_none());
+        assert!(empty.genesis().is_none());
+
+        let chain = transfer_chain(&user1, &key1, &user2);
+        assert!(!chain.is_empty());
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.genesis().unwrap().index, 0);
+        assert_eq!(chain.tip().unwrap().index, 1);
+        assert_eq!(chain.blocks[1].transaction_count(), 3);
+        assert_eq!(
+            chain.total_transaction_count(),
+            chain.blocks[0].transaction_count() + 3
+        );
+        assert_eq!(
+            chain.total_fees(),
+            chain
+                .iter_transactions()
+                .map(|(_, txn)| txn.fee)
+                .sum::<u64>()
+        );
+        assert_eq!(
+            chain.total_supply(),
+            chain
+                .iter_transactions()
+                .filter(|(_, txn)| txn.is_coinbase())
+                .map(|(_, txn)| txn.amount)
+                .sum::<u64>()
+        );
+        assert_eq!(user1.transaction_history(&chain).len(), 6);
+        assert!(user1.transaction_history(&Blockchain::new()).is_empty());
+
+        // user1 was never credited, so the chain overspends
+        assert!(matches!(
+            chain.compute_utxo_set(),
+            Err(ValidationError::InsufficientFunds { available: 0, .. })
+        ));
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        key_registry.insert(user2.address, user2.public_key);
+        assert!(chain.verify_with_keys(&key_registry).is_ok());
+    }
+
+    #[test]
+    fn coinbase_rewards_may_not_exceed_max_supply() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut capped = Blockchain::with_genesis(&user1);
+        assert_eq!(capped.total_supply(), GENESIS_REWARD);
+        let greedy = TxnsBuilder::new()
+            .add_coinbase(&user2, MAX_SUPPLY - GENESIS_REWARD + 1, 1)
+            .build()
+            .unwrap();
+        let genesis_hash = capped.tip().unwrap().hash;
+        assert!(matches!(
+            capped.add_block(Block::new(genesis_hash, greedy, 1)),
+            Err(BlockchainError::Invalid(
+                ValidationError::SupplyCapExceeded {
+                    block_index: 1,
+                    supply,
+                }
+            )) if supply == MAX_SUPPLY + 1
+        ));
+        let exact = TxnsBuilder::new()
+            .add_coinbase(&user2, MAX_SUPPLY - GENESIS_REWARD, 1)
+            .build()
+            .unwrap();
+        capped
+            .add_block(Block::new(genesis_hash, exact, 1))
+            .unwrap();
+        assert_eq!(capped.total_supply(), MAX_SUPPLY);
+        assert!(capped.verify().is_ok());
+
+        let mut overminted = capped.clone();
+        overminted.blocks[0].txns = TxnsBuilder::new()
+            .add_coinbase(&user1, GENESIS_REWARD + 1, 0)
+            .build()
+            .unwrap();
+        overminted.blocks[0].hash(&HashAlgorithm::Blake3);
+        overminted.blocks[1].prev_hash = overminted.blocks[0].hash;
+        overminted.blocks[1].hash(&HashAlgorithm::Blake3);
+        assert!(matches!(
+            overminted.verify(),
+            Err(ValidationError::SupplyCapExceeded { block_index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn transactions_are_found_by_id() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let block1 = chain.get_block_by_index(0).unwrap();
+        assert_eq!(
+            chain.get_block_by_hash(&block1.hash).unwrap().index,
+            0
+        );
+        let t_txn = block1.txns.iter().next().unwrap();
+
+        let (found_in, found) = chain.find_transaction(&t_txn.id).unwrap();
+        assert_eq!((found_in, found.amount), (0, t_txn.amount));
+        assert_eq!(chain.iter_transactions().count(), 6);
+        assert!(chain.find_transaction(&Hash::default()).is_none());
+        let hex_id = t_txn.id.to_string().to_uppercase();
+        assert_eq!(Hash::from_hex(&hex_id), Ok(t_txn.id));
+        let (found_in, found) =
+            chain.find_transaction_by_hex(&hex_id).unwrap().unwrap();
+        assert_eq!((found_in, found.id), (0, t_txn.id));
+        assert_eq!(
+            chain.find_transaction_by_hex(&Hash::default().to_string()),
+            Ok(None)
+        );
+        assert_eq!(
+            chain.find_transaction_by_hex("abcd"),
+            Err(HexError::InvalidLength {
+                expected: HASH_SIZE,
+                got: 2
+            })
+        );
+        assert_eq!(
+            chain.find_transaction_by_hex("not hex"),
+            Err(HexError::OddLength)
+        );
+    }
+
+    #[test]
+    fn signatures_cover_the_signing_pre_image() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let t_txn = Txn::new_signed(&user1, &key1, &user2, COIN);
+        assert!(t_txn.verify(user1.public_key).is_ok());
+        assert!(t_txn.verify_id());
+
+        let mut resigned = t_txn.clone();
+        resigned.signature.clear();
+        assert_eq!(resigned.hash_for_signing(), t_txn.hash_for_signing());
+        resigned.sign(&key1);
+        assert_eq!(resigned.signature, t_txn.signature);
+        resigned.amount += 1;
+        assert!(resigned.verify(user1.public_key).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn transactions_and_blocks_round_trip_through_bytes() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let block1 = &chain.blocks[0];
+        let t_txn = block1.txns.iter().next().unwrap();
+        assert!(block1.verify_hash(&HashAlgorithm::Blake3));
+
+        let bytes = t_txn.to_bytes();
+        assert_eq!(Txn::from_bytes(&bytes).unwrap().id, t_txn.id);
+
+        // Hashes are computed from the same bytes bincode writes, so they
+        // do not depend on the serde feature
+        assert_eq!(bytes, bincode::serialize(t_txn).unwrap());
+        assert_eq!(
+            block1.to_encoded(),
+            bincode::serialize(block1).unwrap()
+        );
+        for block in &chain.blocks {
+            let bytes = block.as_bytes();
+            assert_eq!(bytes.len(), block.size_bytes());
+            assert_eq!(Block::from_bytes(&bytes).unwrap(), *block);
+            assert!(matches!(
+                Block::from_bytes(&bytes[..bytes.len() - 1]),
+                Err(BlockchainError::Serialization(_))
+            ));
+        }
+
+        let mut tampered = bytes.clone();
+        // First sender byte, after the version, id and kind
+        tampered[1 + HASH_SIZE + 4] ^= 1;
+        assert!(matches!(
+            Txn::from_bytes(&tampered),
+            Err(TransactionError::InvalidHash)
+        ));
+        assert!(Txn::from_bytes(&bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn genesis_chains_start_with_a_coinbase_block() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut genesis_chain = Blockchain::with_genesis(&user2);
+        assert_eq!(genesis_chain.height(), 0);
+        let genesis = genesis_chain.genesis().unwrap();
+        assert_eq!(genesis.txns.len(), 1);
+        assert!(genesis.txns.iter().all(Txn::is_coinbase));
+        assert!(genesis_chain.verify().is_ok());
+        assert_eq!(
+            genesis_chain.balance(&user2.address),
+            Some(GENESIS_REWARD)
+        );
+        assert_eq!(genesis_chain.balance(&user1.address), None);
+        assert_eq!(user1.balance(&genesis_chain), 0);
+        assert_eq!(user2.transaction_history(&genesis_chain).len(), 1);
+
+        let stray = Block::new(Hash::default(), Txns::new(), 1);
+        assert!(genesis_chain.add_block(stray).is_err());
+        let tip_hash = genesis_chain.tip().unwrap().hash;
+        let mut tampered = Block::new(tip_hash, Txns::new(), 1);
+        tampered.nonce += 1;
+        assert!(matches!(
+            genesis_chain.validate_block_before_adding(&tampered),
+            Err(BlockchainError::Invalid(ValidationError::HashMismatch))
+        ));
+        let mut rerooted = Block::new(tip_hash, Txns::new(), 1);
+        rerooted.txns.merkle_root = tip_hash;
+        rerooted.hash(&HashAlgorithm::Blake3);
+        assert!(matches!(
+            genesis_chain.add_block(rerooted),
+            Err(BlockchainError::Invalid(
+                ValidationError::MerkleRootMismatch
+            ))
+        ));
+        assert_eq!(genesis_chain.height(), 0);
+    }
+
+    #[test]
+    fn chains_check_with_the_hash_algorithm_they_use() {
+        let (user1, _) = keyed_user("user1");
+        let sha256 = HashAlgorithm::Sha256;
+        let mut sha_chain = Blockchain::with_hash_algorithm(sha256);
+        let mut reward = Txn::new_coinbase(&user1, GENESIS_REWARD, 0);
+        reward.hash(&sha256);
+        let mut sha_txns = Txns::new();
+        sha_txns.add(reward.clone()).unwrap();
+        sha_txns.calc_merkle_root_with(&sha256);
+        let proof =
+            sha_txns.generate_proof_with(&reward.id, &sha256).unwrap();
+        assert!(proof.verify_with(
+            &reward.id,
+            &sha_txns.merkle_root,
+            &sha256
+        ));
+        assert!(matches!(
+            sha_chain.add_block(Block::new(
+                Hash::default(),
+                sha_txns.clone(),
+                0
+            )),
+            Err(BlockchainError::Invalid(ValidationError::HashMismatch))
+        ));
+        let sha_genesis = Block::new_with_hash_algorithm(
+            Hash::default(),
+            sha_txns,
+            0,
+            0,
+            &sha256,
+        );
+        assert!(sha_genesis.validate().is_err());
+        sha_chain.add_block(sha_genesis).unwrap();
+        assert_eq!(sha_chain.verify(), Ok(()));
+        assert_eq!(
+            sha_chain.balance(&user1.address),
+            Some(GENESIS_REWARD)
+        );
+    }
+
+    // Transactions built for a SHA-256 chain pass through the mempool and
+    // into a block that the chain verifies
+    #[test]
+    fn sha256_chains_take_transactions_from_the_mempool() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let sha256 = HashAlgorithm::Sha256;
+        let mut sha_chain = Blockchain::with_hash_algorithm(sha256);
+        let genesis_txns = TxnsBuilder::with_hash_algorithm(sha256)
+            .add_coinbase(&user1, GENESIS_REWARD, 0)
+            .build()
+            .unwrap();
+        sha_chain
+            .add_block(Block::new_with_hash_algorithm(
+                Hash::default(),
+                genesis_txns,
+                0,
+                0,
+                &sha256,
+            ))
+            .unwrap();
+
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        let mut mempool = MemPool::with_keys(3, key_registry)
+            .with_hash_algorithm(sha256);
+        let default_hashed = Txn::new_signed(&user1, &key1, &user2, COIN);
+        assert!(matches!(
+            mempool.add(default_hashed),
+            Err(error::MempoolError::Invalid(
+                ValidationError::StaleTransactionId(_)
+            ))
+        ));
+        let cheap =
+            Txn::new_signed_with(&user1, &key1, &user2, COIN, &sha256);
+        let mut pricey = Txn::new_with(&user1, &user2, COIN, &sha256)
+            .with_fee(2)
+            .with_expiry(u64::MAX);
+        assert!(pricey.verify_id_with(&sha256));
+        pricey.sign(&key1);
+        let pricey_id = pricey.id;
+        mempool.add(cheap).unwrap();
+        mempool.add(pricey).unwrap();
+
+        let mut txns = Txns::new();
+        for txn in mempool.take_best(2) {
+            txns.add(txn).unwrap();
+        }
+        txns.sort_by_fee_descending_with(&sha256);
+        let block = Block::new_with_hash_algorithm(
+            sha_chain.tip().unwrap().hash,
+            txns,
+            1,
+            0,
+            &sha256,
+        );
+        let proof =
+            block.merkle_proof_for_with(&pricey_id, &sha256).unwrap();
+        assert!(proof.verify_with(
+            &pricey_id,
+            &block.txns.merkle_root,
+            &sha256
+        ));
+        sha_chain.add_block(block).unwrap();
+        assert_eq!(sha_chain.verify(), Ok(()));
+        assert_eq!(sha_chain.balance(&user2.address), Some(2 * COIN));
+    }
+
+    #[test]
+    fn blocks_after_genesis_must_meet_the_difficulty() {
+        let mut mined_chain = Blockchain::with_retargeting(1, 10);
+        let genesis = Block::new(Hash::default(), Txns::new(), 0);
+        let genesis_hash = genesis.hash;
+        mined_chain.add_block(genesis).unwrap();
+        let mut unmined = Block::new(genesis_hash, Txns::new(), 1);
+        while unmined.meets_difficulty(1) {
+            unmined.nonce += 1;
+            unmined.hash(&HashAlgorithm::default());
+        }
+        assert!(matches!(
+            mined_chain.add_block(unmined),
+            Err(BlockchainError::Invalid(
+                ValidationError::InsufficientWork {
+                    index: 1,
+                    difficulty: 1
+                }
+            ))
+        ));
+        let mined =
+            Block::new_with_proof_of_work(genesis_hash, Txns::new(), 1, 1);
+        assert!(mined.meets_difficulty(1));
+        mined_chain.add_block(mined).unwrap();
+        assert!(mined_chain.verify().is_ok());
+        mined_chain.initial_difficulty = HASH_SIZE as u8;
+        assert!(matches!(
+            mined_chain.verify(),
+            Err(ValidationError::InsufficientWork { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn fast_blocks_raise_the_difficulty() {
+        let fast_chain = fast_chain();
+        assert_eq!(fast_chain.current_difficulty(), 2);
+        assert!(fast_chain.verify().is_ok());
+        assert_eq!(fast_chain.difficulty_history(), vec![(0, 0), (2, 1)]);
+        let mut slowed = fast_chain.clone();
+        slowed.rollback(1).unwrap();
+        assert_eq!(slowed.current_difficulty(), 1);
+        assert_eq!(Blockchain::new().difficulty_history(), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn only_blocks_after_the_last_verified_are_checked_again() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        assert_eq!(walked.height(), 4);
+        assert_eq!(walked.difficulty_history(), vec![(0, 0)]);
+
+        assert_eq!(walked.last_verified, 4);
+        assert!(walked.verify_incremental(walked.last_verified).is_ok());
+        assert!(walked.verify_incremental(walked.height() + 1).is_ok());
+        let mut tampered = walked.clone();
+        tampered.blocks[2].nonce += 1;
+        assert!(tampered.verify_incremental(3).is_ok());
+        assert!(matches!(
+            tampered.verify_incremental(2),
+            Err(ValidationError::HashMismatch)
+        ));
+        tampered.blocks[2].hash(&HashAlgorithm::Blake3);
+        assert!(matches!(
+            tampered.verify_incremental(3),
+            Err(ValidationError::BrokenChain { .. })
+        ));
+        assert!(tampered.verify_incremental(0).is_err());
+        assert_eq!(walked.fork_at(2).unwrap().last_verified, 2);
+    }
+
+    #[test]
+    fn blocks_before_a_checkpoint_are_trusted() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut checkpointed = walked.clone();
+        assert_eq!(
+            checkpointed.checkpoint(3, walked.blocks[3].hash),
+            Ok(())
+        );
+        assert_eq!(
+            checkpointed.checkpoint(2, Hash::default()),
+            Err(CheckpointError::HashMismatch {
+                index: 2,
+                expected: Hash::default(),
+                got: walked.blocks[2].hash,
+            })
+        );
+        assert_eq!(
+            checkpointed.checkpoint(5, Hash::default()),
+            Err(CheckpointError::MissingBlock(5))
+        );
+        checkpointed.blocks[1].nonce += 1;
+        assert!(checkpointed.verify_incremental(0).is_ok());
+        assert!(checkpointed.verify().is_err());
+
+        // Until the checkpoint leaves the chain
+        checkpointed.rollback(2).unwrap();
+        assert!(checkpointed.verify_incremental(0).is_err());
+    }
+
+    #[test]
+    fn recomputing_every_hash_repairs_a_chain() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut repaired = walked.clone();
+        repaired.blocks[2].txns.iter_mut().nth(1).unwrap().amount += 1;
+        assert!(repaired.verify().is_err());
+        repaired.recompute_all_hashes();
+        assert!(repaired.verify().is_ok());
+        assert_eq!(repaired.blocks[..2], walked.blocks[..2]);
+        assert_ne!(repaired.blocks[2].hash, walked.blocks[2].hash);
+        assert_eq!(repaired.blocks[3].prev_hash, repaired.blocks[2].hash);
+        assert!(repaired
+            .blocks
+            .iter()
+            .all(|block| block.txns.iter().all(|txn| !txn.id_is_stale())));
+        assert_eq!(repaired.last_verified, 0);
+        let tip_hash = repaired.tip().unwrap().hash;
+        assert_eq!(
+            repaired.get_block_by_hash(&tip_hash).unwrap().index,
+            4
+        );
+    }
+
+    #[test]
+    fn walking_a_chain_visits_every_transaction_in_order() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let order: Vec<(u32, Hash)> = walked
+            .walk()
+            .map(|(block, txn)| (block.index, txn.id))
+            .collect();
+        let expected: Vec<(u32, Hash)> = walked
+            .iter_transactions()
+            .map(|(index, txn)| (index, txn.id))
+            .collect();
+        assert_eq!(order, expected);
+        assert_eq!(order.len(), walked.total_transaction_count());
+        assert!(order.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(
+            walked.walk().filter_by_address(&user2.address).count(),
+            8
+        );
+        assert_eq!(
+            walked.walk().filter_by_address(&user1.address).count(),
+            5
+        );
+        let stranger = Address([1; HASH_SIZE]);
+        assert_eq!(walked.walk().filter_by_address(&stranger).count(), 0);
+        assert_eq!(Blockchain::new().walk().next(), None);
+    }
+
+    #[test]
+    fn addresses_are_looked_up_by_their_raw_bytes() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let stranger = Address([1; HASH_SIZE]);
+        let balances: HashMap<Address, u64> =
+            walked.compute_utxo_set().unwrap();
+        let user2_balance = balances.get(&user2.address);
+        assert!(user2_balance.is_some());
+        assert_eq!(balances.get(&user2.address.0[..]), user2_balance);
+        assert_eq!(balances.get(&stranger.0[..]), None);
+        let ids: HashSet<Hash> =
+            walked.walk().map(|(_, txn)| txn.id).collect();
+        assert_eq!(ids.len(), walked.total_transaction_count());
+        let first_id = walked.blocks[0].txns.iter().next().unwrap().id;
+        assert!(ids.contains(&first_id) && ids.contains(&first_id.0[..]));
+        assert!(!ids.contains(&stranger.0[..]));
+
+        assert_eq!(
+            walked.transaction_count_by_address(&user1.address)
+                + walked.received_count_by_address(&user1.address),
+            5
+        );
+        assert_eq!(walked.transaction_count_by_address(&stranger), 0);
+        assert_eq!(
+            walked.address_first_seen(&user1.address),
+            Some(walked.blocks[0].timestamp)
+        );
+        assert_eq!(
+            walked.address_first_seen(&user2.address),
+            Some(walked.blocks[1].timestamp)
+        );
+        assert_eq!(
+            walked.address_last_seen(&user2.address),
+            Some(walked.tip().unwrap().timestamp)
+        );
+        assert_eq!(walked.address_first_seen(&stranger), None);
+        assert_eq!(walked.address_last_seen(&Address::default()), None);
+    }
+
+    #[test]
+    fn the_tip_can_be_rolled_back_but_not_past_genesis() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut rolled = walked.clone();
+        let removed = rolled.rollback(3).unwrap();
+        assert_eq!(rolled.height(), walked.height() - 3);
+        let removed_indexes: Vec<u32> =
+            removed.iter().map(|block| block.index).collect();
+        assert_eq!(removed_indexes, vec![2, 3, 4]);
+        assert!(rolled.get_block_by_hash(&removed[0].hash).is_none());
+        assert!(rolled.verify().is_ok());
+        assert!(
+            rolled.balance(&user2.address)
+                < walked.balance(&user2.address)
+        );
+        assert!(matches!(
+            rolled.rollback(2),
+            Err(BlockchainError::CannotRollbackGenesis)
+        ));
+        assert_eq!(rolled.rollback(0).unwrap().len(), 0);
+        for block in removed {
+            rolled.add_block(block).unwrap();
+        }
+        assert_eq!(rolled, walked);
+    }
+
+    #[test]
+    fn blocks_from_elsewhere_must_form_a_verified_chain() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let synced =
+            Blockchain::from_blocks(walked.blocks.clone()).unwrap();
+        assert_eq!(synced.height(), walked.height());
+        assert_eq!(
+            synced.current_difficulty(),
+            walked.current_difficulty()
+        );
+        assert!(synced
+            .get_block_by_hash(&walked.blocks[2].hash)
+            .is_some());
+        let mut gapped = walked.blocks.clone();
+        gapped.remove(2);
+        assert!(matches!(
+            Blockchain::from_blocks(gapped),
+            Err(BlockchainError::Invalid(
+                ValidationError::UnexpectedIndex {
+                    expected: 2,
+                    got: 3
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn batches_stop_at_the_first_block_that_fails() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut batched = Blockchain::new();
+        let applied = batched
+            .apply_blocks(walked.blocks[..3].iter().cloned())
+            .unwrap();
+        assert_eq!((applied, batched.height()), (3, 2));
+
+        // Only started if it links up to the tip
+        assert!(matches!(
+            batched.apply_blocks(walked.blocks[4..].iter().cloned()),
+            Err(BlockchainError::BatchFailed { applied: 0, .. })
+        ));
+        assert_eq!(batched.height(), 2);
+        let mut forged = walked.blocks[3..].to_vec();
+        forged[1].nonce += 1; // Still links up, but its hash no longer matches
+        assert!(matches!(
+            batched.apply_blocks(forged.into_iter()),
+            Err(BlockchainError::BatchFailed { applied: 1, .. })
+        ));
+        assert_eq!(batched.height(), 3);
+        assert_eq!(batched.apply_blocks(std::iter::empty()).unwrap(), 0);
+    }
+
+    #[test]
+    fn orphans_wait_for_their_parent() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let mut reordered = Blockchain::new();
+        reordered.add_block(walked.blocks[0].clone()).unwrap();
+        reordered.add_orphan(walked.blocks[3].clone());
+        reordered.add_orphan(walked.blocks[2].clone());
+        reordered.add_orphan(walked.blocks[2].clone());
+        assert_eq!((reordered.orphan_count(), reordered.height()), (2, 0));
+        reordered.add_block(walked.blocks[1].clone()).unwrap();
+        assert_eq!((reordered.orphan_count(), reordered.height()), (0, 3));
+        assert_eq!(reordered.blocks[..], walked.blocks[..4]);
+
+        // Orphans whose parent never comes are dropped
+        let mut impatient = Blockchain::new();
+        impatient.orphan_ttl = 1;
+        impatient.add_block(walked.blocks[0].clone()).unwrap();
+        impatient.add_orphan(walked.blocks[4].clone());
+        impatient.add_block(walked.blocks[1].clone()).unwrap();
+        assert_eq!(impatient.orphan_count(), 1);
+        impatient.add_block(walked.blocks[2].clone()).unwrap();
+        assert_eq!(impatient.orphan_count(), 0);
+    }
+
+    #[test]
+    fn blocks_over_the_size_limit_are_refused() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut walked = walked_chain(&user1, &key1, &user2);
+        let mut oversized = Txns::new();
+        for amount in 0..3_000 {
+            let txn = Txn::new(&user1, &user2, amount)
+                .with_metadata(&[0; MAX_METADATA_SIZE])
+                .unwrap();
+            oversized.add(txn).unwrap();
+        }
+        oversized.batch_sign(&key1);
+        oversized.calc_merkle_root();
+        let tip_hash = walked.tip().unwrap().hash;
+        let big_block =
+            Block::new(tip_hash, oversized, walked.height() + 1);
+        assert_eq!(big_block.size_bytes(), big_block.to_encoded().len());
+        assert!(big_block.size_bytes() > MAX_BLOCK_SIZE);
+        assert!(matches!(
+            walked.add_block(big_block.clone()),
+            Err(BlockchainError::BlockTooLarge {
+                max: MAX_BLOCK_SIZE,
+                ..
+            })
+        ));
+
+        // Nor are they accepted from a peer
+        let mut synced = walked.blocks.clone();
+        synced.push(big_block);
+        assert!(matches!(
+            Blockchain::from_blocks(synced),
+            Err(BlockchainError::BlockTooLarge {
+                max: MAX_BLOCK_SIZE,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn mined_transactions_cannot_be_replayed() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut replayed = transfer_chain(&user1, &key1, &user2);
+        let t_txn = replayed.blocks[0].txns.iter().next().unwrap().clone();
+        let fresh = Txn::new_signed(&user1, &key1, &user2, COIN);
+        assert!(fresh.double_spend_check(&replayed).is_ok());
+        assert!(matches!(
+            t_txn.double_spend_check(&replayed),
+            Err(TransactionError::AlreadyIncluded { block_index: 0 })
+        ));
+        let mut replay_txns = Txns::new();
+        replay_txns.add(t_txn).unwrap();
+        replay_txns.calc_merkle_root();
+        let prev_hash = replayed.blocks[1].hash;
+        let replay_block = Block::new(prev_hash, replay_txns, 2);
+        assert!(matches!(
+            replayed.add_block(replay_block.clone()),
+            Err(BlockchainError::Invalid(
+                ValidationError::DuplicateTransaction {
+                    block_index: 0,
+                    ..
+                }
+            ))
+        ));
+        assert_eq!(replayed.height(), 1);
+
+        // Nor can they be replayed in blocks from a peer
+        let mut synced = replayed.blocks.clone();
+        synced.push(replay_block);
+        assert!(matches!(
+            Blockchain::from_blocks(synced),
+            Err(BlockchainError::Invalid(
+                ValidationError::DuplicateTransaction {
+                    block_index: 0,
+                    ..
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn time_lookups_find_the_tip_at_that_moment() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut timed = Blockchain::new();
+        for index in 0..3 {
+            let prev_hash =
+                timed.tip().map_or(Hash::default(), |b| b.hash);
+            let mut block = Block::new(prev_hash, Txns::new(), index);
+            block.timestamp = 1_000 * (index as u128 + 1);
+            block.hash(&HashAlgorithm::Blake3);
+            timed.add_block(block).unwrap();
+        }
+        assert!(timed.block_at_timestamp(999).is_none());
+        assert_eq!(timed.block_at_timestamp(1_000).unwrap().index, 0);
+        assert_eq!(timed.block_at_timestamp(2_999).unwrap().index, 1);
+        assert_eq!(timed.block_at_timestamp(u128::MAX).unwrap().index, 2);
+        let in_range = |start, end| -> Vec<u32> {
+            timed
+                .find_blocks_by_time_range(start, end)
+                .iter()
+                .map(|block| block.index)
+                .collect()
+        };
+        assert_eq!(in_range(1_000, 2_000), vec![0, 1]);
+        assert_eq!(in_range(1_001, u128::MAX), vec![1, 2]);
+        assert_eq!(in_range(2_000, 2_000), vec![1]);
+        assert!(in_range(0, 999).is_empty());
+        assert!(in_range(3_001, u128::MAX).is_empty());
+        assert!(in_range(2_000, 1_000).is_empty());
+        assert!(timed
+            .find_transactions_by_time_range(0, u128::MAX)
+            .is_empty());
+
+        let walked = walked_chain(&user1, &key1, &user2);
+        assert_eq!(
+            walked.find_transactions_by_time_range(0, u128::MAX).len(),
+            walked.total_transaction_count()
+        );
+
+        let stats = timed.statistics();
+        assert_eq!(stats.block_count, 3);
+        assert_eq!(stats.transaction_count, 0);
+        assert_eq!(stats.oldest_block_timestamp, 1_000);
+        assert_eq!(stats.newest_block_timestamp, 3_000);
+        assert_eq!(stats.average_block_time_ms, 1_000.0);
+        let stats = walked.statistics();
+        assert_eq!(stats.transaction_count, 9);
+        assert_eq!(stats.total_transferred, COIN * (1 + 2 + 3 + 4));
+        assert_eq!(stats.unique_addresses, 2);
+        assert_eq!(
+            Blockchain::new().statistics().average_block_time_ms,
+            0.0
+        );
+    }
+
+    #[test]
+    fn clones_are_independent_of_the_original() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let mut cloned = chain.clone();
+        let tip_hash = cloned.blocks[1].hash;
+        cloned
+            .add_block(Block::new(tip_hash, Txns::new(), 2))
+            .unwrap();
+        cloned.blocks[0].timestamp += 1;
+        assert_eq!(chain.height(), 1);
+        assert!(chain.verify().is_ok());
+        assert!(cloned.verify().is_err());
+        assert_eq!(user1.clone().address, user1.address);
+    }
+
+    #[test]
+    fn the_longest_chain_is_the_one_with_most_work() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+
+        // Forks share history up to the fork point
+        let fork = chain.fork_at(0).unwrap();
+        assert_eq!(fork.height(), 0);
+        assert_eq!(chain.height(), 1);
+        assert!(chain.is_longer_than(&fork));
+        assert!(std::ptr::eq(
+            Blockchain::longest_chain(&fork, &chain),
+            &chain
+        ));
+        assert_eq!(chain.common_ancestor(&fork), Some(0));
+        assert_eq!(
+            Blockchain::with_genesis(&user2).common_ancestor(&fork),
+            None
+        );
+        assert!(chain.fork_at(2).is_none());
+
+        // Between equal heights, the chain with more work wins
+        let mut easy = fork.clone();
+        let mut hard = fork.clone();
+        let tip_hash = fork.tip().unwrap().hash;
+        let mut easy_block = Block::new(tip_hash, Txns::new(), 1);
+        while easy_block.meets_difficulty(1) {
+            easy_block.nonce += 1; // Keep it below the hard block's work
+            easy_block.hash(&HashAlgorithm::default());
+        }
+        easy.add_block(easy_block).unwrap();
+        hard.add_block(Block::new_with_proof_of_work(
+            tip_hash,
+            Txns::new(),
+            1,
+            1,
+        ))
+        .unwrap();
+        assert!(hard.cumulative_work() > easy.cumulative_work());
+        assert!(std::ptr::eq(
+            Blockchain::longest_chain(&easy, &hard),
+            &hard
+        ));
+        assert!(std::ptr::eq(
+            Blockchain::longest_chain(&hard, &easy),
+            &hard
+        ));
+        assert!(std::ptr::eq(
+            Blockchain::longest_chain(&easy, &easy),
+            &easy
+        ));
+    }
+
+    #[test]
+    fn ids_hashes_and_addresses_display_as_hex() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let t_txn = chain.blocks[0].txns.iter().next().unwrap();
+        assert_eq!(t_txn.to_hex_id(), t_txn.id.to_string());
+        assert_eq!(t_txn.to_hex_id().len(), 2 * HASH_SIZE);
+        assert!(t_txn.to_string().contains(&t_txn.to_hex_id()));
+        let hex_block = &chain.blocks[1];
+        assert_eq!(hex_block.to_hex_hash(), hex_block.hash.to_string());
+        assert!(hex_block.to_string().contains(&hex_block.to_hex_hash()));
+        assert_eq!(
+            util::hex_to_bytes(&user1.to_hex_address()).unwrap(),
+            user1.address.0
+        );
+        assert!(user1.to_string().contains(&user1.to_hex_address()));
+        assert_eq!(display_amount(t_txn.amount), "10.00000000");
+        assert_eq!(display_amount(COIN / 10 + COIN / 5), "0.30000000");
+    }
+
+    #[test]
+    fn public_keys_round_trip_through_lowercase_hex() {
+        let (user1, _) = keyed_user("user1");
+        let key_hex = user1.public_key_hex();
+        assert_eq!(key_hex.len(), 2 * PUBLIC_KEY_LENGTH);
+        assert_eq!(key_hex, key_hex.to_lowercase());
+        assert!(user1.to_string().contains(&key_hex));
+        assert_eq!(
+            User::from_public_key_hex(&key_hex).unwrap(),
+            user1.public_key
+        );
+        assert_eq!(
+            User::from_public_key_hex(&key_hex.to_uppercase()).unwrap(),
+            user1.public_key
+        );
+        assert!(matches!(
+            User::from_public_key_hex(&key_hex[2..]),
+            Err(UserError::InvalidHex(HexError::InvalidLength {
+                expected: PUBLIC_KEY_LENGTH,
+                got: 31
+            }))
+        ));
+        assert!(matches!(
+            User::from_public_key_hex("zz"),
+            Err(UserError::InvalidHex(HexError::InvalidDigit('z')))
+        ));
+    }
+
+    #[test]
+    fn multisig_needs_distinct_signatories() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let (user3, key3) = keyed_user("user3");
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        key_registry.insert(user2.address, user2.public_key);
+        let mut multisig_registry = key_registry.clone();
+        multisig_registry.insert(user3.address, user3.public_key);
+
+        // 2 of 3
+        let mut escrow = Txn::new_multisig(
+            Address::default(),
+            &user1,
+            COIN,
+            2,
+            vec![user1.address, user2.address, user3.address],
+        );
+        escrow.add_signature(&key2).unwrap();
+        assert!(matches!(
+            escrow.add_signature(&key2),
+            Err(TransactionError::AlreadySigned)
+        ));
+        assert_eq!(
+            escrow.verify_signatures(&multisig_registry),
+            Err(ValidationError::InsufficientSignatures {
+                required: 2,
+                valid: 1
+            })
+        );
+        escrow.add_signature(&key3).unwrap();
+        assert_eq!(escrow.multisig_count(), 2);
+        assert!(escrow.verify_signatures(&multisig_registry).is_ok());
+        assert!(escrow.verify_signatures(&key_registry).is_err());
+        assert_eq!(
+            escrow
+                .verify_and_recover_sender(&multisig_registry)
+                .unwrap(),
+            Address::default()
+        );
+    }
+
+    #[test]
+    fn verifying_through_the_registry_gives_back_the_sender() {
+        let (user2, key2) = keyed_user("user2");
+        let (user3, _) = keyed_user("user3");
+        let mut registry = HashMap::new();
+        registry.insert(user2.address, user2.public_key);
+        let paid = Txn::new_signed(&user2, &key2, &user3, COIN);
+        assert_eq!(
+            paid.verify_and_recover_sender(&registry).unwrap(),
+            user2.address
+        );
+        assert!(matches!(
+            paid.verify_and_recover_sender(&HashMap::new()),
+            Err(TransactionError::Invalid(ValidationError::UnknownSender(
+                sender
+            ))) if sender == user2.address
+        ));
+        assert!(matches!(
+            Txn::new_coinbase(&user3, COIN, 1)
+                .verify_and_recover_sender(&registry),
+            Err(TransactionError::Coinbase)
+        ));
+    }
+
+    #[test]
+    fn transfers_are_checked_against_the_balances_they_spend() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let mut ledger = Blockchain::with_genesis(&user2);
+        let spend =
+            Txn::new_signed(&user2, &key2, &user1, GENESIS_REWARD / 2);
+        ledger.apply_transaction(&spend, &user2.public_key).unwrap();
+        let overspend =
+            Txn::new_signed(&user2, &key2, &user1, GENESIS_REWARD);
+        assert!(matches!(
+            ledger.apply_transaction(&overspend, &user2.public_key),
+            Err(TransactionError::Invalid(
+                ValidationError::InsufficientFunds { .. }
+            ))
+        ));
+        assert!(matches!(
+            ledger.apply_transaction(&spend, &user1.public_key),
+            Err(TransactionError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn a_balance_splits_into_a_payment_and_change() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let (change_to, _) = keyed_user("change");
+        let mut split_ledger = Blockchain::with_genesis(&user2);
+        let utxo_set = split_ledger.compute_utxo_set().unwrap();
+        let (payment, change) = Txn::split(
+            &user2,
+            &key2,
+            &user1,
+            COIN,
+            &change_to.address,
+            &utxo_set,
+        )
+        .unwrap();
+        assert_eq!(
+            (payment.recipient, payment.amount),
+            (user1.address, COIN)
+        );
+        assert_eq!(
+            (change.recipient, change.amount),
+            (change_to.address, GENESIS_REWARD - COIN)
+        );
+
+        // Which together spend all of it
+        for txn in [&payment, &change] {
+            split_ledger
+                .apply_transaction(txn, &user2.public_key)
+                .unwrap();
+        }
+        let broke = Txn::new_signed(&user2, &key2, &user1, 1);
+        assert!(split_ledger
+            .apply_transaction(&broke, &user2.public_key)
+            .is_err());
+        assert!(matches!(
+            Txn::split(
+                &user2,
+                &key2,
+                &user1,
+                GENESIS_REWARD,
+                &change_to.address,
+                &utxo_set
+            ),
+            Err(TransactionError::Invalid(
+                ValidationError::InsufficientFunds { .. }
+            ))
+        ));
+        assert!(matches!(
+            Txn::split(
+                &user2,
+                &key2,
+                &user1,
+                COIN,
+                &user2.address,
+                &utxo_set
+            ),
+            Err(TransactionError::Invalid(ValidationError::SelfTransfer(
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn a_longer_branch_replaces_the_tip() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let fork = chain.fork_at(0).unwrap();
+        let mut branch = chain.fork_at(0).unwrap();
+        for index in 1..3 {
+            let prev_hash = branch.tip().unwrap().hash;
+            branch
+                .add_block(Block::new(prev_hash, Txns::new(), index))
+                .unwrap();
+        }
+        let mut reorged = chain.clone();
+        assert!(matches!(
+            reorged.reorg(fork),
+            Err(ReorgError::ShorterChain)
+        ));
+        assert!(matches!(
+            reorged.reorg(fast_chain()),
+            Err(ReorgError::NoCommonAncestor)
+        ));
+        let orphaned = reorged.reorg(branch).unwrap();
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].txns.len(), 3);
+        assert_eq!(reorged.height(), 2);
+    }
+
+    // A genesis for user2, a transfer of half of it to user1, and two
+    // empty blocks, pruned up to the second
+    fn pruned_ledger(
+        user1: &User,
+        user2: &User,
+        key2: &Keypair,
+    ) -> (Blockchain, Blockchain) {
+        let mut ledger = Blockchain::with_genesis(user2);
+        let spend =
+            Txn::new_signed(user2, key2, user1, GENESIS_REWARD / 2);
+        ledger.apply_transaction(&spend, &user2.public_key).unwrap();
+        let mut pruned = ledger.clone();
+        for _ in 0..2 {
+            let prev_hash = pruned.tip().unwrap().hash;
+            let index = pruned.height() + 1;
+            pruned
+                .add_block(Block::new(prev_hash, Txns::new(), index))
+                .unwrap();
+        }
+        assert!(matches!(
+            pruned.prune(pruned.height() + 1),
+            Err(BlockchainError::PruneOutOfRange { .. })
+        ));
+        assert!(pruned.prune(0).unwrap().is_empty());
+        assert!(!pruned.is_pruned());
+        assert_eq!(pruned.prune(2).unwrap().len(), 1);
+        (ledger, pruned)
+    }
+
+    #[test]
+    fn pruning_keeps_balances_and_difficulty() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let (ledger, pruned) = pruned_ledger(&user1, &user2, &key2);
+        assert!(pruned.is_pruned());
+        assert!(pruned.get_block_by_index(1).is_none());
+        assert_eq!(pruned.get_block_by_index(2).unwrap().index, 2);
+        assert!(pruned.verify().is_ok());
+        assert_eq!(
+            pruned.balance(&user1.address),
+            ledger.balance(&user1.address)
+        );
+
+        let mut pruned_fast = fast_chain();
+        pruned_fast.prune(2).unwrap();
+        assert!(pruned_fast.verify().is_ok());
+        assert_eq!(pruned_fast.current_difficulty(), 2);
+    }
+
+    // Pruned state read from a file may claim a retarget after the
+    // pruned blocks, which would otherwise underflow the schedule
+    #[test]
+    fn retargets_past_the_pruned_blocks_are_refused() {
+        let mut pruned_fast = fast_chain();
+        pruned_fast.prune(2).unwrap();
+        pruned_fast.pruned_state.last_retarget_index = 5;
+        assert_eq!(
+            pruned_fast.verify(),
+            Err(ValidationError::InvalidRetargetIndex {
+                last_retarget_index: 5,
+                tip: 1,
+            })
+        );
+
+        pruned_fast.last_retarget_index = 5;
+        let tip_hash = pruned_fast.tip().unwrap().hash;
+        let next =
+            Block::new_with_proof_of_work(tip_hash, Txns::new(), 3, 2);
+        assert!(matches!(
+            pruned_fast.add_block(next),
+            Err(BlockchainError::Invalid(
+                ValidationError::InvalidRetargetIndex { tip: 3, .. }
+            ))
+        ));
+        assert_eq!(pruned_fast.height(), 2);
+    }
+
+    #[test]
+    fn snapshots_restore_balances_and_the_tip() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let (_, pruned) = pruned_ledger(&user1, &user2, &key2);
+        let snap = pruned.snapshot().unwrap();
+        assert_eq!(snap.tip_index, pruned.height());
+        #[cfg(feature = "serde_json")]
+        assert_eq!(
+            BlockchainSnapshot::from_json(&snap.to_json().unwrap())
+                .unwrap(),
+            snap
+        );
+
+        // Without any blocks
+        let mut restored = Blockchain::from_snapshot(snap.clone());
+        assert!(restored.is_pruned());
+        assert!(restored.is_empty());
+        assert_eq!(restored.height(), pruned.height());
+        assert_eq!(
+            restored.balance(&user1.address),
+            pruned.balance(&user1.address)
+        );
+        assert_eq!(restored.snapshot().unwrap(), snap);
+        let next =
+            Block::new(snap.tip_hash, Txns::new(), snap.tip_index + 1);
+        restored.add_block(next).unwrap();
+        assert!(restored.verify().is_ok());
+        assert_eq!(restored.height(), snap.tip_index + 1);
+    }
+
+    #[test]
+    fn metadata_is_part_of_the_id() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let signed = Txn::new_signed(&user1, &key1, &user2, COIN);
+
+        // So adding it needs a fresh signature
+        let mut tagged =
+            signed.clone().with_metadata(b"bridge:42").unwrap();
+        assert_ne!(tagged.id, signed.id);
+        assert!(!tagged.is_signed() && tagged.verify_id());
+        tagged.sign(&key1);
+        assert!(tagged.verify(user1.public_key).is_ok());
+
+        assert!(matches!(
+            signed.clone().with_metadata(&[0; MAX_METADATA_SIZE + 1]),
+            Err(TransactionError::Invalid(
+                ValidationError::MetadataTooLarge(257)
+            ))
+        ));
+        let mut bloated = signed;
+        bloated.metadata = Some(vec![0; MAX_METADATA_SIZE + 1]);
+        bloated.hash(&HashAlgorithm::Blake3);
+        let mut bloated_txns = Txns::new();
+        bloated_txns.add(bloated).unwrap();
+        bloated_txns.calc_merkle_root();
+        assert_eq!(
+            Block::new(Hash::default(), bloated_txns, 0).validate(),
+            Err(ValidationError::MetadataTooLarge(MAX_METADATA_SIZE + 1))
+        );
+    }
+
+    #[test]
+    fn edited_transactions_are_stale_until_rehashed() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut tagged = Txn::new(&user1, &user2, COIN)
+            .with_metadata(b"bridge:42")
+            .unwrap();
+        tagged.sign(&key1);
+        let mut edited = tagged.clone();
+        edited.metadata = Some(b"bridge:43".to_vec());
+        assert!(edited.id_is_stale() && !tagged.id_is_stale());
+        assert_ne!(edited.canonical_id(), tagged.id);
+        let mut edited_txns = Txns::new();
+        edited_txns.add(edited.clone()).unwrap();
+        edited_txns.calc_merkle_root();
+        assert_eq!(
+            Block::new(Hash::default(), edited_txns, 0).validate(),
+            Err(ValidationError::StaleTransactionId(tagged.id))
+        );
+        edited.hash(&HashAlgorithm::default());
+        assert_eq!(edited.id, edited.canonical_id());
+        assert!(!edited.id_is_stale());
+    }
+
+    #[test]
+    fn expired_transactions_may_not_be_mined() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let now = now_millis() as u64;
+        let stale = Txn::new(&user1, &user2, COIN).with_expiry(now - 1);
+        assert!(stale.is_expired(now));
+        assert!(!Txn::new(&user1, &user2, COIN).is_expired(now));
+        let mut expiring = Txns::new();
+        expiring.add(stale.clone()).unwrap();
+        expiring
+            .add(Txn::new(&user1, &user2, COIN).with_expiry(now + 60_000))
+            .unwrap();
+        expiring.batch_sign(&key1);
+        expiring.calc_merkle_root();
+
+        let mut stale_chain = fast_chain();
+        stale_chain.prune(2).unwrap();
+        let prev_hash = stale_chain.tip().unwrap().hash;
+        let stale_block = Block::new_with_proof_of_work(
+            prev_hash,
+            expiring,
+            stale_chain.height() + 1,
+            stale_chain.current_difficulty(),
+        );
+        stale_chain.add_block(stale_block).unwrap();
+        assert_eq!(
+            stale_chain.verify(),
+            Err(ValidationError::ExpiredTransaction(stale.id))
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_round_trips_with_hashes_as_hex() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let block1 = &chain.blocks[0];
+        let t_txn = block1.txns.iter().next().unwrap();
+
+        let json = chain.to_json().unwrap();
+        assert!(json.contains(&block1.hash.to_string()));
+        let from_json = Blockchain::from_json(&json).unwrap();
+        assert_eq!(from_json, chain);
+        assert!(from_json.get_block_by_hash(&block1.hash).is_some());
+        let json = block1.to_json().unwrap();
+        assert_eq!(&Block::from_json(&json).unwrap(), block1);
+        let json = t_txn.to_json().unwrap();
+        assert!(json.contains(&format!(
+            "\"{}\"",
+            util::bytes_to_hex(&t_txn.signature)
+        )));
+        assert_eq!(&Txn::from_json(&json).unwrap(), t_txn);
+        assert!(Txn::from_json(&json)
+            .unwrap()
+            .verify(user1.public_key)
+            .is_ok());
+        let json = user1.to_json().unwrap();
+        assert_eq!(User::from_json(&json).unwrap(), user1);
+
+        let mut tagged =
+            t_txn.clone().with_metadata(b"bridge:42").unwrap();
+        tagged.sign(&key1);
+        let json = tagged.to_json().unwrap();
+        assert!(json.contains(&util::bytes_to_hex(b"bridge:42")));
+        assert_eq!(Txn::from_json(&json).unwrap(), tagged);
+
+        let address: Address = user1.address.to_string().parse().unwrap();
+        assert_eq!(address, user1.address);
+        assert!("00ff".parse::<Address>().is_err());
+        let hash: Hash = block1.hash.to_string().parse().unwrap();
+        assert_eq!(hash, block1.hash);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chains_round_trip_through_disk_and_streams() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let block1 = &chain.blocks[0];
+        let (_dir, cfg) = temp_cfg();
+        let chain_path = cfg.data_dir.join("blockchain.bin");
+        chain.to_disk(&chain_path).unwrap();
+        let loaded = Blockchain::from_disk(&chain_path).unwrap();
+        assert_eq!(
+            bincode::serialize(&loaded).unwrap(),
+            bincode::serialize(&chain).unwrap()
+        );
+        assert!(loaded.get_block_by_hash(&block1.hash).is_some());
+
+        let mut stream = Vec::new();
+        chain.serialize_to_writer(&mut stream).unwrap();
+        assert_eq!(stream, bincode::serialize(&chain).unwrap());
+        let streamed =
+            Blockchain::deserialize_from_reader(&stream[..]).unwrap();
+        assert_eq!(streamed, chain);
+        assert!(streamed.get_block_by_hash(&block1.hash).is_some());
+        assert!(
+            Blockchain::deserialize_from_reader(&stream[..10]).is_err()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn corrupted_chain_files_are_caught_before_decoding() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let (_dir, cfg) = temp_cfg();
+        let chain_path = cfg.data_dir.join("blockchain.bin");
+        chain.to_disk(&chain_path).unwrap();
+
+        let mut file = fs::read(&chain_path).unwrap();
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        fs::write(&chain_path, &file).unwrap();
+        assert!(matches!(
+            Blockchain::from_disk(&chain_path),
+            Err(BlockchainError::Storage(StorageError::CorruptedFile))
+        ));
+        fs::write(&chain_path, &file[..20]).unwrap();
+        assert!(matches!(
+            Blockchain::from_disk(&chain_path),
+            Err(BlockchainError::Storage(StorageError::CorruptedFile))
+        ));
+    }
+
+    // Files from before they were sealed are only read by the migrations
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unsealed_files_need_the_legacy_migration() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let (_dir, cfg) = temp_cfg();
+        let chain_path = cfg.data_dir.join("blockchain.bin");
+        fs::write(&chain_path, to_v1_bytes(&chain)).unwrap();
+        assert!(matches!(
+            Blockchain::from_disk(&chain_path),
+            Err(BlockchainError::Storage(StorageError::CorruptedFile))
+        ));
+        assert_eq!(
+            Blockchain::migrate_legacy_file(&chain_path).unwrap(),
+            chain
+        );
+        assert_eq!(Blockchain::from_disk(&chain_path).unwrap(), chain);
+        assert_eq!(
+            Blockchain::migrate_legacy_file(&chain_path).unwrap(),
+            chain
+        );
+
+        fs::write(User::path("user2", &cfg), user2.to_bytes().unwrap())
+            .unwrap();
+        assert!(matches!(
+            User::from_uid("user2", &cfg),
+            Err(BlockchainError::Storage(StorageError::CorruptedFile))
+        ));
+        assert_eq!(
+            User::migrate_legacy_file("user2", &cfg).unwrap(),
+            user2
+        );
+        assert_eq!(User::from_uid("user2", &cfg).unwrap(), user2);
+        assert!(User::migrate_legacy_file("../user2", &cfg).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn unknown_chain_schemas_are_refused() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let (_dir, cfg) = temp_cfg();
+        let chain_path = cfg.data_dir.join("blockchain.bin");
+        let mut payload = 3u32.to_le_bytes().to_vec();
+        payload.extend(bincode::serialize(&chain).unwrap());
+        fs::write(&chain_path, storage::seal(&payload)).unwrap();
+        assert!(matches!(
+            Blockchain::from_disk(&chain_path),
+            Err(BlockchainError::Migration(
+                error::MigrationError::UnsupportedSchema(3)
+            ))
+        ));
+
+        // Older schemas are migrated on the way in
+        let mut payload = 1u32.to_le_bytes().to_vec();
+        payload.extend(to_v1_bytes(&chain));
+        fs::write(&chain_path, storage::seal(&payload)).unwrap();
+        assert_eq!(Blockchain::from_disk(&chain_path).unwrap(), chain);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn compacting_a_current_chain_changes_nothing() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let compacted = chain.compact().unwrap();
+        assert_eq!(compacted, chain);
+        assert!(compacted.verify().is_ok());
+        assert_eq!(compacted.last_verified, compacted.height());
+        let walked = walked_chain(&user1, &key1, &user2);
+        assert_eq!(walked.compact().unwrap(), walked);
+    }
+
+    #[test]
+    fn timestamps_are_wall_clock_time() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let now = now_millis();
+        assert!(now - chain.blocks[1].timestamp < 60_000);
+        assert!(
+            now - chain.blocks[0].txns.iter().next().unwrap().timestamp
+                < 60_000
+        );
+    }
 }
@@ -0,0 +1,193 @@
+use crate::error::MempoolError;
+use crate::hash::HashAlgorithm;
+use crate::validation::ValidationError;
+use crate::{Address, Blockchain, Hash, Txn};
+use ed25519_dalek::PublicKey;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+// Transactions waiting to be included in a block
+pub struct MemPool {
+    pending: Vec<Txn>,
+    max_size: usize,
+    key_registry: HashMap<Address, PublicKey>,
+    hash_algorithm: HashAlgorithm, // The chain's, for checking ids
+}
+
+impl MemPool {
+    pub fn new(max_size: usize) -> Self {
+        Self::with_keys(max_size, HashMap::new())
+    }
+
+    // With a registry, every transfer must be signed by a sender in it
+    pub fn with_keys(
+        max_size: usize,
+        key_registry: HashMap<Address, PublicKey>,
+    ) -> Self {
+        Self {
+            pending: Vec::new(),
+            max_size,
+            key_registry,
+            hash_algorithm: HashAlgorithm::default(),
+        }
+    }
+
+    // For a chain that does not use the default algorithm
+    pub fn with_hash_algorithm(
+        mut self,
+        hash_algorithm: HashAlgorithm,
+    ) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    pub fn add(&mut self, txn: Txn) -> Result<(), MempoolError> {
+        if self.pending.iter().any(|pending| pending.id == txn.id) {
+            return Err(MempoolError::Duplicate(txn.id));
+        }
+        if self.pending.len() >= self.max_size {
+            return Err(MempoolError::Full);
+        }
+        if txn.id_is_stale_with(&self.hash_algorithm) {
+            return Err(ValidationError::StaleTransactionId(txn.id).into());
+        }
+        if txn.is_multisig() {
+            txn.verify_signatures(&self.key_registry)?;
+        } else if !self.key_registry.is_empty() && !txn.is_coinbase() {
+            if !txn.is_signed() {
+                return Err(ValidationError::UnsignedTransaction.into());
+            }
+            let key = self
+                .key_registry
+                .get(&txn.sender)
+                .ok_or(ValidationError::UnknownSender(txn.sender))?;
+            txn.verify(*key)?;
+        }
+
+        self.pending.push(txn);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &Hash) -> Option<Txn> {
+        let i = self.pending.iter().position(|txn| txn.id == *id)?;
+        Some(self.pending.remove(i))
+    }
+
+    // Remove and return the n highest-fee transactions. Ties keep
+    // their arrival order.
+    pub fn take_best(&mut self, n: usize) -> Vec<Txn> {
+        self.pending.sort_by_key(|txn| Reverse(txn.fee));
+        let n = n.min(self.pending.len());
+        let mut best = Vec::with_capacity(n);
+        best.extend(self.pending.drain(..n));
+        best
+    }
+
+    // Drop everything the chain already contains
+    pub fn purge_included(&mut self, chain: &Blockchain) {
+        let included: HashSet<Hash> =
+            chain.iter_transactions().map(|(_, txn)| txn.id).collect();
+        self.pending.retain(|txn| !included.contains(&txn.id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{keyed_user, transfer_chain};
+    use crate::COIN;
+
+    // Pending transactions leave the pool by fee, or once mined
+    #[test]
+    fn transactions_leave_by_fee_or_once_mined() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let chain = transfer_chain(&user1, &key1, &user2);
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        key_registry.insert(user2.address, user2.public_key);
+
+        let mut mempool = MemPool::with_keys(3, key_registry);
+        let mut cheap = Txn::new(&user1, &user2, COIN).with_fee(1);
+        cheap.sign(&key1);
+        let cheap_id = cheap.id;
+        mempool.add(cheap).unwrap();
+        let mut pricey = Txn::new(&user1, &user2, COIN).with_fee(5);
+        pricey.sign(&key1);
+        mempool.add(pricey).unwrap();
+        mempool
+            .add(chain.blocks[1].txns.iter().next().unwrap().clone())
+            .unwrap();
+        assert!(matches!(
+            mempool.add(Txn::new(&user2, &user1, COIN)),
+            Err(MempoolError::Full)
+        ));
+        mempool.purge_included(&chain);
+        assert_eq!(mempool.len(), 2);
+        let best = mempool.take_best(1);
+        assert_eq!(best[0].fee, 5);
+        assert_eq!(mempool.remove(&cheap_id).unwrap().fee, 1);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn forged_transactions_are_refused() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        let mut mempool = MemPool::with_keys(3, key_registry);
+        let mut forged = Txn::new_signed(&user1, &key1, &user2, COIN);
+        assert!(forged.is_signed());
+        forged.amount += 1;
+        assert!(matches!(
+            mempool.add(forged),
+            Err(MempoolError::Invalid(_))
+        ));
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn registries_refuse_unsigned_unknown_and_stale_transfers() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        let mut mempool = MemPool::with_keys(3, key_registry);
+        assert!(matches!(
+            mempool.add(Txn::new(&user1, &user2, COIN)),
+            Err(MempoolError::Invalid(
+                ValidationError::UnsignedTransaction
+            ))
+        ));
+        assert!(matches!(
+            mempool.add(Txn::new_signed(&user2, &key2, &user1, COIN)),
+            Err(MempoolError::Invalid(ValidationError::UnknownSender(
+                sender
+            ))) if sender == user2.address
+        ));
+        let mut stale = Txn::new_signed(&user1, &key1, &user2, COIN);
+        stale.fee += 1;
+        assert!(matches!(
+            mempool.add(stale.clone()),
+            Err(MempoolError::Invalid(
+                ValidationError::StaleTransactionId(id)
+            )) if id == stale.id
+        ));
+        assert!(mempool.is_empty());
+
+        // Without a registry only stale ids are refused
+        let mut open = MemPool::new(3);
+        open.add(Txn::new(&user1, &user2, COIN)).unwrap();
+        assert!(open.add(stale).is_err());
+        assert_eq!(open.len(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::hash::HashAlgorithm;
+use crate::{Hash, HASH_SIZE};
+
+// Which side of the path a sibling hash sits on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+// The sibling hashes from a leaf up to the merkle root, lowest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub siblings: Vec<(Side, Hash)>,
+    pub block: Option<(Hash, u32)>, // Hash and index, if made from a block
+}
+
+impl MerkleProof {
+    // Recompute the root from the leaf and compare
+    pub fn verify(&self, txn_id: &Hash, root: &Hash) -> bool {
+        self.verify_with(txn_id, root, &HashAlgorithm::default())
+    }
+
+    pub fn verify_with(
+        &self,
+        txn_id: &Hash,
+        root: &Hash,
+        algorithm: &HashAlgorithm,
+    ) -> bool {
+        let computed =
+            self.siblings.iter().fold(*txn_id, |node, (side, sibling)| {
+                match side {
+                    Side::Left => hash_pair(sibling, &node, algorithm),
+                    Side::Right => hash_pair(&node, sibling, algorithm),
+                }
+            });
+        computed == *root
+    }
+}
+
+// The parent of two nodes in the merkle tree
+pub fn hash_pair(
+    left: &Hash,
+    right: &Hash,
+    algorithm: &HashAlgorithm,
+) -> Hash {
+    let mut concat: [u8; HASH_SIZE * 2] = [0; HASH_SIZE * 2];
+    concat[..HASH_SIZE].copy_from_slice(left.as_ref());
+    concat[HASH_SIZE..].copy_from_slice(right.as_ref());
+    algorithm.digest(&concat)
+}
@@ -0,0 +1,271 @@
+use crate::encode::Encode;
+use crate::error::MigrationError;
+use crate::{
+    Address, Block, Blockchain, Hash, HashAlgorithm, Hashable, Txn,
+    TxnKind,
+};
+use crate::{CHAIN_SCHEMA_VERSION, TXN_VERSION};
+use serde::{Deserialize, Serialize};
+
+// Brings a transaction serialized in an older layout up to the current one
+pub trait TxnMigration: Sized {
+    fn migrate(
+        bytes: &[u8],
+        from_version: u8,
+    ) -> Result<Self, MigrationError>;
+}
+
+// A plain transfer as written before there was a version byte, fees,
+// transaction kinds, expiries or metadata
+#[derive(Serialize, Deserialize)]
+struct TxnV0 {
+    id: Hash,
+    sender: Address,
+    recipient: Address,
+    amount: u64,
+    timestamp: u128,
+    #[serde(with = "crate::hex::vec")]
+    signature: Vec<u8>,
+}
+
+impl TxnV0 {
+    // Its id was the hash of the rest of it, without the signature
+    fn verify_id(&self) -> bool {
+        let unhashed = TxnV0 {
+            id: Hash::default(),
+            signature: Vec::new(),
+            ..*self
+        };
+        let bytes = bincode::serialize(&unhashed)
+            .expect("Could not serialize transaction");
+        HashAlgorithm::default().digest(&bytes) == self.id
+    }
+}
+
+impl TxnMigration for Txn {
+    // The id changes with the layout and the old signature covered the old
+    // layout, so a migrated transaction has a new id and must be signed
+    // again by its sender
+    fn migrate(
+        bytes: &[u8],
+        from_version: u8,
+    ) -> Result<Self, MigrationError> {
+        match from_version {
+            0 => {
+                let old: TxnV0 = bincode::deserialize(bytes)?;
+                if !old.verify_id() {
+                    return Err(MigrationError::InvalidHash);
+                }
+                let mut txn = Txn {
+                    version: TXN_VERSION,
+                    id: Hash::default(),
+                    kind: TxnKind::Transfer,
+                    sender: old.sender,
+                    recipient: old.recipient,
+                    amount: old.amount,
+                    fee: 0,
+                    timestamp: old.timestamp,
+                    expiry: None,
+                    metadata: None,
+                    signature: Vec::new(),
+                };
+                txn.hash(&HashAlgorithm::default());
+                Ok(txn)
+            }
+            TXN_VERSION => {
+                let txn: Txn = bincode::deserialize(bytes)?;
+                if !txn.verify_id() {
+                    return Err(MigrationError::InvalidHash);
+                }
+                Ok(txn)
+            }
+            version => Err(MigrationError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+// Serialize a transaction in the version 0 layout, for checking that old
+// data still reads back
+pub(crate) fn to_v0_bytes(txn: &Txn) -> Vec<u8> {
+    let mut old = TxnV0 {
+        id: Hash::default(),
+        sender: txn.sender,
+        recipient: txn.recipient,
+        amount: txn.amount,
+        timestamp: txn.timestamp,
+        signature: Vec::new(),
+    };
+    old.id = HashAlgorithm::default().digest(
+        &bincode::serialize(&old)
+            .expect("Could not serialize transaction"),
+    );
+    bincode::serialize(&old).expect("Could not serialize transaction")
+}
+
+// A block's hash under version 1, which covered the transactions
+// themselves rather than their merkle root
+pub(crate) fn v1_block_hash(
+    block: &Block,
+    algorithm: &HashAlgorithm,
+) -> Hash {
+    let mut bytes = Vec::new();
+    block.prev_hash.encode(&mut bytes);
+    block.txns.encode(&mut bytes);
+    block.index.encode(&mut bytes);
+    block.timestamp.encode(&mut bytes);
+    block.nonce.encode(&mut bytes);
+    algorithm.digest(&bytes)
+}
+
+// Version 2 block hashes cover the merkle root, so every block is checked
+// against its version 1 hash, then hashed and mined again
+pub fn migrate_v1_to_v2(bytes: &[u8]) -> Result<Vec<u8>, MigrationError> {
+    let mut blockchain: Blockchain = bincode::deserialize(bytes)?;
+    blockchain.rehash_blocks(v1_block_hash)?;
+    Ok(bincode::serialize(&blockchain)?)
+}
+
+// Bring a chain file payload written as `from_version` up to
+// CHAIN_SCHEMA_VERSION, one version at a time
+pub fn migrate_chain(
+    bytes: &[u8],
+    from_version: u32,
+) -> Result<Vec<u8>, MigrationError> {
+    if from_version == 0 || from_version > CHAIN_SCHEMA_VERSION {
+        return Err(MigrationError::UnsupportedSchema(from_version));
+    }
+    let mut bytes = bytes.to_vec();
+    for version in from_version..CHAIN_SCHEMA_VERSION {
+        bytes = match version {
+            1 => migrate_v1_to_v2(&bytes)?,
+            version => {
+                return Err(MigrationError::UnsupportedSchema(version))
+            }
+        };
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{
+        fast_chain, keyed_user, to_v1_bytes, walked_chain,
+    };
+    use crate::txns::Txns;
+    use crate::{CanSerialize, COIN};
+
+    // Transactions written before the version byte come back as unsigned
+    // version 1 transfers with the same contents
+    #[test]
+    fn version_0_transactions_migrate() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let txn = Txn::new_signed(&user1, &key1, &user2, COIN);
+        let migrated = Txn::migrate(&to_v0_bytes(&txn), 0).unwrap();
+        assert_eq!(migrated.version, TXN_VERSION);
+        assert_eq!(
+            (migrated.sender, migrated.amount, migrated.timestamp),
+            (txn.sender, txn.amount, txn.timestamp)
+        );
+        assert!(migrated.verify_id() && !migrated.is_signed());
+        assert!(matches!(
+            Txn::migrate(&txn.to_bytes(), 2),
+            Err(MigrationError::UnsupportedVersion(2))
+        ));
+    }
+
+    // Version 1 chains come back hashed and mined again, with the same
+    // transactions
+    #[test]
+    fn version_1_chains_are_rehashed() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        for chain in [walked_chain(&user1, &key1, &user2), fast_chain()] {
+            let v1 = to_v1_bytes(&chain);
+            let mut old: Blockchain = bincode::deserialize(&v1).unwrap();
+            old.reindex();
+            assert!(old.verify().is_err());
+
+            let mut migrated: Blockchain =
+                bincode::deserialize(&migrate_chain(&v1, 1).unwrap())
+                    .unwrap();
+            migrated.reindex();
+            assert!(migrated.verify().is_ok());
+            assert_eq!(
+                migrated.current_difficulty(),
+                chain.current_difficulty()
+            );
+            assert!(migrated
+                .blocks
+                .iter()
+                .zip(&chain.blocks)
+                .all(|(migrated, block)| migrated.txns == block.txns));
+        }
+    }
+
+    // Migrating must not turn a chain that was invalid under version 1
+    // into a valid version 2 one
+    #[test]
+    fn forged_version_1_chains_are_refused() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let walked = walked_chain(&user1, &key1, &user2);
+        let v1: Blockchain =
+            bincode::deserialize(&to_v1_bytes(&walked)).unwrap();
+
+        // A bigger coinbase reward under the block's old hash
+        let mut inflated = v1.clone();
+        let mut reward =
+            inflated.blocks[2].txns.iter().next().unwrap().clone();
+        reward.amount += COIN;
+        reward.hash(&HashAlgorithm::default());
+        let mut txns = Txns::new();
+        txns.add(reward).unwrap();
+        inflated.blocks[2].txns = txns;
+
+        let mut unlinked = v1;
+        unlinked.blocks[3].prev_hash = unlinked.blocks[1].hash;
+        unlinked.blocks[3].hash =
+            v1_block_hash(&unlinked.blocks[3], &HashAlgorithm::default());
+
+        // Relinked under version 1 rules, but without the work
+        let mut unmined: Blockchain =
+            bincode::deserialize(&to_v1_bytes(&fast_chain())).unwrap();
+        let block = &mut unmined.blocks[2];
+        while block.meets_difficulty(1) {
+            block.nonce += 1;
+            block.hash = v1_block_hash(block, &HashAlgorithm::default());
+        }
+
+        for (forged, bad_index) in
+            [(inflated, 2), (unlinked, 3), (unmined, 2)]
+        {
+            assert!(matches!(
+                migrate_v1_to_v2(&bincode::serialize(&forged).unwrap()),
+                Err(MigrationError::InvalidBlock(i)) if i == bad_index
+            ));
+        }
+    }
+
+    #[test]
+    fn chains_migrate_from_known_schemas_only() {
+        let payload = b"chain".to_vec();
+        assert_eq!(
+            migrate_chain(&payload, CHAIN_SCHEMA_VERSION).unwrap(),
+            payload
+        );
+        assert!(matches!(
+            migrate_chain(&payload, 1),
+            Err(MigrationError::Serialization(_))
+        ));
+        assert!(matches!(
+            migrate_chain(&payload, 0),
+            Err(MigrationError::UnsupportedSchema(0))
+        ));
+        assert!(matches!(
+            migrate_chain(&payload, CHAIN_SCHEMA_VERSION + 1),
+            Err(MigrationError::UnsupportedSchema(_))
+        ));
+    }
+}
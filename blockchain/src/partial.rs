@@ -0,0 +1,192 @@
+use crate::error::TransactionError;
+use crate::validation::ValidationError;
+use crate::{Address, HashAlgorithm, Hashable, Txn, TxnKind, TXN_VERSION};
+use ed25519_dalek::{Keypair, PublicKey};
+use sha2::Sha512;
+
+// A transaction put together in stages, in the spirit of Bitcoin's PSBTs.
+// One party creates it and each signer adds a signature, with sign or
+// over signing_bytes elsewhere, such as on a hardware wallet. Copies are
+// combined by appending their signatures, and Txn::from_partial finalizes
+// it. Every field is fixed at creation, the timestamp included, so all
+// signers sign the same transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialTxn {
+    pub kind: TxnKind, // A transfer, or a multi-sig
+    pub sender: Address,
+    pub recipient: Address,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: u128,
+    pub signatures: Vec<(PublicKey, Vec<u8>)>,
+}
+
+impl PartialTxn {
+    pub fn new(
+        sender: Address,
+        recipient: Address,
+        amount: u64,
+        fee: u64,
+    ) -> Self {
+        Self {
+            kind: TxnKind::Transfer,
+            sender,
+            recipient,
+            amount,
+            fee,
+            timestamp: crate::now_millis(),
+            signatures: Vec::new(),
+        }
+    }
+
+    // The transaction as it will be, without its signatures
+    fn unsigned(&self) -> Txn {
+        let mut txn = Txn {
+            version: TXN_VERSION,
+            id: Default::default(),
+            kind: self.kind.clone(),
+            sender: self.sender,
+            recipient: self.recipient,
+            amount: self.amount,
+            fee: self.fee,
+            timestamp: self.timestamp,
+            expiry: None,
+            metadata: None,
+            signature: Vec::new(),
+        };
+        txn.hash(&HashAlgorithm::default());
+        txn
+    }
+
+    // What each signer signs
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        self.unsigned().hash_for_signing()
+    }
+
+    pub fn sign(&mut self, keypair: &Keypair) {
+        let signature = keypair.sign::<Sha512>(&self.signing_bytes());
+        self.signatures
+            .push((keypair.public, signature.to_bytes().to_vec()));
+    }
+}
+
+impl Txn {
+    // Finalize a partial transaction once it has every signature it
+    // needs: the sender's for a transfer, or `required` of the
+    // signatories' for a multi-sig. Every signature given must be valid,
+    // and signatures from anyone else are dropped.
+    pub fn from_partial(
+        partial: PartialTxn,
+    ) -> Result<Txn, TransactionError> {
+        let mut txn = partial.unsigned();
+        for (key, signature) in &partial.signatures {
+            txn.verify_one(signature, *key)
+                .map_err(|_| TransactionError::InvalidSignature)?;
+        }
+        let signature_from = |address: &Address| {
+            partial
+                .signatures
+                .iter()
+                .find(|(key, _)| Address::from_public_key(key) == *address)
+                .map(|(_, signature)| signature.as_slice())
+        };
+
+        let (required, signatures) = match &txn.kind {
+            TxnKind::Transfer => (1, vec![signature_from(&txn.sender)]),
+            TxnKind::CoinbaseReward { .. } => {
+                return Err(TransactionError::Coinbase)
+            }
+            TxnKind::MultiSig {
+                required,
+                signatories,
+            } => (
+                *required,
+                signatories.iter().map(signature_from).collect(),
+            ),
+        };
+        let signatures: Vec<&[u8]> =
+            signatures.into_iter().flatten().collect();
+        let valid = signatures.len().min(u8::MAX as usize) as u8;
+        if valid < required {
+            return Err(ValidationError::InsufficientSignatures {
+                required,
+                valid,
+            }
+            .into());
+        }
+        txn.signature = signatures.concat();
+        Ok(txn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::keyed_user;
+    use crate::{COIN, SIGNATURE_SIZE};
+    use std::collections::HashMap;
+
+    // Partial transactions are finalized once enough signers have signed
+    #[test]
+    fn multisig_partials_finalize_with_enough_signatures() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let (user3, key3) = keyed_user("user3");
+        let mut registry = HashMap::new();
+        for user in [&user1, &user2, &user3] {
+            registry.insert(user.address, user.public_key);
+        }
+        let mut staged = PartialTxn {
+            kind: TxnKind::MultiSig {
+                required: 2,
+                signatories: vec![
+                    user1.address,
+                    user2.address,
+                    user3.address,
+                ],
+            },
+            ..PartialTxn::new(Address::default(), user1.address, COIN, 1)
+        };
+        staged.sign(&key2);
+        assert_eq!(
+            Txn::from_partial(staged.clone()).unwrap_err().to_string(),
+            "invalid transaction: 1 of 2 required signatures are valid"
+        );
+
+        // Copies are combined by appending their signatures
+        let mut cosigned = staged.clone();
+        cosigned.signatures = Vec::new();
+        cosigned.sign(&key3);
+        staged.signatures.extend(cosigned.signatures);
+        let finalized = Txn::from_partial(staged.clone()).unwrap();
+        assert!(finalized.verify_id() && finalized.fee == 1);
+        assert_eq!(finalized.multisig_count(), 2);
+        assert!(finalized.verify_signatures(&registry).is_ok());
+
+        let mut forged = staged;
+        forged.signatures[1].0 = user1.public_key;
+        assert!(matches!(
+            Txn::from_partial(forged),
+            Err(TransactionError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn transfers_need_the_senders_signature() {
+        let (user2, key2) = keyed_user("user2");
+        let (user3, key3) = keyed_user("user3");
+        let mut transfer =
+            PartialTxn::new(user2.address, user3.address, COIN, 0);
+        transfer.sign(&key3);
+        assert!(Txn::from_partial(transfer.clone()).is_err());
+
+        // Signed elsewhere, over the signing bytes
+        let external = key2.sign::<Sha512>(&transfer.signing_bytes());
+        transfer
+            .signatures
+            .push((key2.public, external.to_bytes().to_vec()));
+        let transferred = Txn::from_partial(transfer).unwrap();
+        assert!(transferred.verify(user2.public_key).is_ok());
+        assert_eq!(transferred.signature.len(), SIGNATURE_SIZE);
+    }
+}
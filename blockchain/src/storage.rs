@@ -0,0 +1,79 @@
+use crate::error::StorageError;
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+// Files are written as [magic][keyed blake3 MAC][payload length][payload],
+// with the length little-endian and the MAC covering length and payload
+const FILE_MAGIC: &[u8; 4] = b"XBC1";
+const MAC_CONTEXT: &str = "xoreo blockchain 2020-06-01 persisted file MAC";
+const HEADER_SIZE: usize = 4 + 32 + 4;
+
+// Where users and their secret keys are kept on disk
+pub struct StorageConfig {
+    pub data_dir: PathBuf,
+    pub secret_dir: PathBuf,
+}
+
+impl StorageConfig {
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(
+        data_dir: P,
+        secret_dir: Q,
+    ) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            secret_dir: secret_dir.into(),
+        }
+    }
+}
+
+// The `data/` and `secret/` directories next to the binary's cwd
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::new("data", "secret")
+    }
+}
+
+// Not a secret: the MAC catches truncated or corrupted files, it does not
+// stop anyone from forging one
+fn mac_key() -> [u8; 32] {
+    let mut key = [0; 32];
+    blake3::derive_key(MAC_CONTEXT, FILE_MAGIC, &mut key);
+    key
+}
+
+fn mac(length_and_payload: &[u8]) -> blake3::Hash {
+    blake3::keyed_hash(&mac_key(), length_and_payload)
+}
+
+// Wrap a payload for writing to disk
+pub fn seal(payload: &[u8]) -> Vec<u8> {
+    let mut body = (payload.len() as u32).to_le_bytes().to_vec();
+    body.extend_from_slice(payload);
+
+    let mut file = FILE_MAGIC.to_vec();
+    file.extend_from_slice(mac(&body).as_bytes());
+    file.extend(body);
+    file
+}
+
+// True if the file starts like one written by seal. Files written before
+// the wrapper existed do not, and only the legacy migrations read those.
+pub fn is_sealed(file: &[u8]) -> bool {
+    file.starts_with(FILE_MAGIC)
+}
+
+// Check a file written by seal and return its payload
+pub fn unseal(file: &[u8]) -> Result<&[u8], StorageError> {
+    if !is_sealed(file) || file.len() < HEADER_SIZE {
+        return Err(StorageError::CorruptedFile);
+    }
+
+    let stored: [u8; 32] = file[4..36].try_into().unwrap();
+    let body = &file[36..];
+    let length = u32::from_le_bytes(body[..4].try_into().unwrap());
+    // blake3::Hash compares in constant time
+    if mac(body) != stored || body.len() - 4 != length as usize {
+        return Err(StorageError::CorruptedFile);
+    }
+    Ok(&body[4..])
+}
@@ -0,0 +1,638 @@
+use crate::error::TransactionError;
+use crate::hash::HashAlgorithm;
+use crate::merkle::{self, MerkleProof, Side};
+use crate::validation::ValidationError;
+use crate::{Address, Hash, Txn, User, MAX_TXN_SIZE};
+use ed25519_dalek::{Keypair, PublicKey};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Txns {
+    txns: Vec<Txn>,
+    pub merkle_root: Hash,
+}
+
+impl Txns {
+    pub fn new() -> Self {
+        Self {
+            txns: Vec::new(),
+            merkle_root: Hash::default(),
+        }
+    }
+
+    // Room for `capacity` transactions before the set reallocates
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            txns: Vec::with_capacity(capacity),
+            merkle_root: Hash::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.txns.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> &mut Self {
+        self.txns.reserve(additional);
+        self
+    }
+
+    // Refuses a transaction over MAX_TXN_SIZE, so one cannot take up a
+    // whole block
+    pub fn add(&mut self, txn: Txn) -> Result<(), TransactionError> {
+        let size = txn.payload_size_bytes();
+        if size > MAX_TXN_SIZE {
+            return Err(TransactionError::TooLarge { size });
+        }
+        self.txns.push(txn);
+        Ok(())
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Txn> {
+        self.txns.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Txn> {
+        self.txns.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txns.is_empty()
+    }
+
+    // Drop every transaction that has expired by `now`, returning how
+    // many were dropped. Like the other changes to the set below, the
+    // merkle root is recomputed, with the default algorithm unless the
+    // _with variant is given the chain's.
+    pub fn remove_expired(&mut self, now: u64) -> usize {
+        self.remove_expired_with(now, &HashAlgorithm::default())
+    }
+
+    pub fn remove_expired_with(
+        &mut self,
+        now: u64,
+        algorithm: &HashAlgorithm,
+    ) -> usize {
+        let before = self.txns.len();
+        self.txns.retain(|txn| !txn.is_expired(now));
+        self.calc_merkle_root_with(algorithm);
+        before - self.txns.len()
+    }
+
+    // Drop every repeat of a transaction id, keeping the first, and
+    // return how many were dropped
+    pub fn dedup(&mut self) -> usize {
+        self.dedup_with(&HashAlgorithm::default())
+    }
+
+    pub fn dedup_with(&mut self, algorithm: &HashAlgorithm) -> usize {
+        let before = self.txns.len();
+        let mut seen = HashSet::new();
+        self.txns.retain(|txn| seen.insert(txn.id));
+        self.calc_merkle_root_with(algorithm);
+        before - self.txns.len()
+    }
+
+    // Highest fee first, for filling a block with the most profitable
+    // transactions. A coinbase reward stays first and ties keep their
+    // order. The merkle root is recomputed, so any copy of the old root,
+    // such as in a block header, must be updated too.
+    pub fn sort_by_fee_descending(&mut self) -> &mut Self {
+        self.sort_by_fee_descending_with(&HashAlgorithm::default())
+    }
+
+    pub fn sort_by_fee_descending_with(
+        &mut self,
+        algorithm: &HashAlgorithm,
+    ) -> &mut Self {
+        self.txns.sort_by_key(|txn| {
+            (!txn.is_coinbase(), std::cmp::Reverse(txn.fee))
+        });
+        self.calc_merkle_root_with(algorithm);
+        self
+    }
+
+    // Oldest first, otherwise the same as sort_by_fee_descending
+    pub fn sort_by_timestamp_ascending(&mut self) -> &mut Self {
+        self.sort_by_timestamp_ascending_with(&HashAlgorithm::default())
+    }
+
+    pub fn sort_by_timestamp_ascending_with(
+        &mut self,
+        algorithm: &HashAlgorithm,
+    ) -> &mut Self {
+        self.txns
+            .sort_by_key(|txn| (!txn.is_coinbase(), txn.timestamp));
+        self.calc_merkle_root_with(algorithm);
+        self
+    }
+
+    // Sign every transaction that has no signature yet. Ids do not cover
+    // signatures, so the merkle root stays the same.
+    pub fn batch_sign(&mut self, keypair: &Keypair) {
+        self.txns
+            .iter_mut()
+            .filter(|txn| !txn.is_coinbase() && !txn.is_signed())
+            .for_each(|txn| txn.sign(keypair));
+    }
+
+    // Coinbase rewards are never signed, so they are left out
+    pub fn all_signed(&self) -> bool {
+        self.txns
+            .iter()
+            .all(|txn| txn.is_coinbase() || txn.is_signed())
+    }
+
+    // The first id that appears more than once
+    pub fn first_duplicate(&self) -> Option<Hash> {
+        let mut seen = HashSet::new();
+        self.txns
+            .iter()
+            .find(|txn| !seen.insert(txn.id))
+            .map(|txn| txn.id)
+    }
+
+    pub fn verify(&self) -> Result<(), ValidationError> {
+        // A coinbase reward may only be the first transaction
+        if let Some(i) =
+            self.txns.iter().skip(1).position(Txn::is_coinbase)
+        {
+            return Err(ValidationError::MisplacedCoinbase(i + 1));
+        }
+        match self.txns.iter().find(|txn| txn.is_self_transfer()) {
+            Some(txn) => Err(ValidationError::SelfTransfer(txn.sender)),
+            None => Ok(()),
+        }
+    }
+
+    // Check the signatures of every transaction against the registry,
+    // reporting every failure rather than just the first
+    pub fn verify_all_signatures(
+        &self,
+        key_registry: &HashMap<Address, PublicKey>,
+    ) -> Result<(), ValidationError> {
+        let failures: Vec<(Hash, ValidationError)> = self
+            .txns
+            .iter()
+            .filter(|txn| !txn.is_coinbase())
+            .filter_map(|txn| {
+                txn.verify_signatures(key_registry)
+                    .err()
+                    .map(|e| (txn.id, e))
+            })
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::MultipleInvalidSignatures(failures))
+        }
+    }
+
+    pub fn calc_merkle_root_r(
+        leaves: &mut Vec<Hash>,
+        algorithm: &HashAlgorithm,
+    ) -> Hash {
+        if leaves.is_empty() {
+            return Hash::default();
+        }
+
+        if leaves.len() == 1 {
+            return *leaves
+                .first()
+                .expect("Could not get last transaction");
+        }
+
+        if !leaves.len().is_multiple_of(2) {
+            leaves.push(
+                *leaves.last().expect("Could not get last transaction"),
+            );
+        }
+
+        let mut branches: Vec<Hash> = Vec::new();
+
+        for i in (0..leaves.len() - 1).step_by(2) {
+            branches.push(merkle::hash_pair(
+                &leaves[i],
+                &leaves[i + 1],
+                algorithm,
+            ));
+        }
+        Txns::calc_merkle_root_r(&mut branches, algorithm)
+    }
+
+    // Compute the merkle root without storing it
+    pub fn compute_merkle_root(&self) -> Hash {
+        self.compute_merkle_root_with(&HashAlgorithm::default())
+    }
+
+    pub fn compute_merkle_root_with(
+        &self,
+        algorithm: &HashAlgorithm,
+    ) -> Hash {
+        let mut merkle_leaves: Vec<Hash> =
+            self.txns.iter().map(|txn| txn.id).collect();
+        Txns::calc_merkle_root_r(&mut merkle_leaves, algorithm)
+    }
+
+    pub fn calc_merkle_root(&mut self) {
+        self.calc_merkle_root_with(&HashAlgorithm::default());
+    }
+
+    pub fn calc_merkle_root_with(&mut self, algorithm: &HashAlgorithm) {
+        self.merkle_root = self.compute_merkle_root_with(algorithm);
+    }
+
+    pub fn generate_proof(&self, txn_id: &Hash) -> Option<MerkleProof> {
+        self.generate_proof_with(txn_id, &HashAlgorithm::default())
+    }
+
+    // Walk the same tree as calc_merkle_root_r, recording the sibling
+    // of the transaction's ancestor at each level
+    pub fn generate_proof_with(
+        &self,
+        txn_id: &Hash,
+        algorithm: &HashAlgorithm,
+    ) -> Option<MerkleProof> {
+        let mut pos =
+            self.txns.iter().position(|txn| txn.id == *txn_id)?;
+        let mut level: Vec<Hash> =
+            self.txns.iter().map(|txn| txn.id).collect();
+        let mut siblings = Vec::new();
+
+        while level.len() > 1 {
+            if !level.len().is_multiple_of(2) {
+                level
+                    .push(*level.last().expect("Could not get last hash"));
+            }
+
+            if pos.is_multiple_of(2) {
+                siblings.push((Side::Right, level[pos + 1]));
+            } else {
+                siblings.push((Side::Left, level[pos - 1]));
+            }
+
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    merkle::hash_pair(&pair[0], &pair[1], algorithm)
+                })
+                .collect();
+            pos /= 2;
+        }
+
+        Some(MerkleProof {
+            siblings,
+            block: None,
+        })
+    }
+}
+
+// Builds a signed, verified transaction set with its merkle root already
+// calculated. The first transaction that could not be added is reported
+// by build.
+pub struct TxnsBuilder {
+    txns: Txns,
+    error: Option<TransactionError>,
+    hash_algorithm: HashAlgorithm, // For the ids and the merkle root
+}
+
+impl TxnsBuilder {
+    pub fn new() -> Self {
+        Self::with_hash_algorithm(HashAlgorithm::default())
+    }
+
+    // For a chain that does not use the default algorithm
+    pub fn with_hash_algorithm(hash_algorithm: HashAlgorithm) -> Self {
+        Self {
+            txns: Txns::new(),
+            error: None,
+            hash_algorithm,
+        }
+    }
+
+    fn add(mut self, txn: Txn) -> Self {
+        if let Err(e) = self.txns.add(txn) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    pub fn add_transfer(
+        self,
+        sender: &User,
+        keypair: &Keypair,
+        recipient: &User,
+        amount: u64,
+        fee: u64,
+    ) -> Self {
+        let mut txn =
+            Txn::new_with(sender, recipient, amount, &self.hash_algorithm)
+                .with_fee(fee);
+        txn.sign(keypair);
+        self.add(txn)
+    }
+
+    pub fn add_coinbase(
+        self,
+        recipient: &User,
+        reward: u64,
+        block_index: u32,
+    ) -> Self {
+        let coinbase = Txn::new_coinbase_with(
+            recipient,
+            reward,
+            block_index,
+            &self.hash_algorithm,
+        );
+        self.add(coinbase)
+    }
+
+    pub fn build(mut self) -> Result<Txns, TransactionError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.txns.is_empty() {
+            return Err(ValidationError::EmptyTransactionSet.into());
+        }
+        self.txns.verify()?;
+        self.txns.calc_merkle_root_with(&self.hash_algorithm);
+        Ok(self.txns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::Encode;
+    use crate::tests::keyed_user;
+    use crate::{now_millis, HASH_SIZE};
+    use crate::{Block, COIN, GENESIS_REWARD};
+
+    #[test]
+    fn signed_sets_verify_against_the_registry() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut signed = Txns::new();
+        let mut unsigned = Txns::new();
+        for amount in [10, 11, 12] {
+            signed
+                .add(Txn::new_signed(&user1, &key1, &user2, amount * COIN))
+                .unwrap();
+            unsigned
+                .add(Txn::new(&user1, &user2, (amount + 10) * COIN))
+                .unwrap();
+        }
+        signed.calc_merkle_root();
+        unsigned.calc_merkle_root();
+        assert!(signed.verify().is_ok() && unsigned.verify().is_ok());
+        assert_ne!(signed.merkle_root, unsigned.merkle_root);
+
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        key_registry.insert(user2.address, user2.public_key);
+        assert!(signed.verify_all_signatures(&key_registry).is_ok());
+        match unsigned.verify_all_signatures(&key_registry) {
+            Err(ValidationError::MultipleInvalidSignatures(failures)) => {
+                assert_eq!(failures.len(), 3)
+            }
+            other => {
+                panic!("expected signature failures, got {:?}", other)
+            }
+        }
+        assert!(matches!(
+            signed.verify_all_signatures(&HashMap::new()),
+            Err(ValidationError::MultipleInvalidSignatures(_))
+        ));
+    }
+
+    #[test]
+    fn batch_signing_keeps_the_root() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut txns = TxnsBuilder::new()
+            .add_coinbase(&user1, GENESIS_REWARD, 0)
+            .build()
+            .unwrap();
+        for amount in [20, 21, 22] {
+            txns.add(Txn::new(&user1, &user2, amount * COIN)).unwrap();
+        }
+        txns.calc_merkle_root();
+        assert!(!txns.all_signed());
+        let unsigned_root = txns.merkle_root;
+        txns.batch_sign(&key1);
+        assert!(txns.all_signed());
+        assert_eq!(txns.merkle_root, unsigned_root);
+        assert!(txns.iter().next().unwrap().signature.is_empty());
+
+        let mut key_registry = HashMap::new();
+        key_registry.insert(user1.address, user1.public_key);
+        assert!(txns.verify_all_signatures(&key_registry).is_ok());
+    }
+
+    #[test]
+    fn the_builder_signs_orders_and_roots_a_set() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let built = TxnsBuilder::new()
+            .add_coinbase(&user1, GENESIS_REWARD, 0)
+            .add_transfer(&user1, &key1, &user2, COIN, 1)
+            .build()
+            .unwrap();
+        assert_eq!(built.merkle_root, built.compute_merkle_root());
+        assert!(built.iter().skip(1).all(Txn::is_signed));
+        assert!(matches!(
+            TxnsBuilder::new().build(),
+            Err(TransactionError::Invalid(
+                ValidationError::EmptyTransactionSet
+            ))
+        ));
+        assert!(TxnsBuilder::new()
+            .add_transfer(&user1, &key1, &user2, COIN, 1)
+            .add_coinbase(&user1, GENESIS_REWARD, 0)
+            .build()
+            .is_err());
+
+        // Sending to yourself is refused, with or without a fee
+        assert!(matches!(
+            TxnsBuilder::new()
+                .add_transfer(&user1, &key1, &user2, COIN, 0)
+                .add_transfer(&user1, &key1, &user1, COIN, 1)
+                .build(),
+            Err(TransactionError::Invalid(ValidationError::SelfTransfer(
+                sender
+            ))) if sender == user1.address
+        ));
+    }
+
+    #[test]
+    fn changing_any_leaf_changes_the_root() {
+        let leaves: Vec<Hash> =
+            (0..5u8).map(|i| Hash([i; HASH_SIZE])).collect();
+        let root = Txns::calc_merkle_root_r(
+            &mut leaves.clone(),
+            &HashAlgorithm::Blake3,
+        );
+        for i in 0..leaves.len() {
+            let mut tampered = leaves.clone();
+            tampered[i].0[0] ^= 1;
+            assert_ne!(
+                Txns::calc_merkle_root_r(
+                    &mut tampered,
+                    &HashAlgorithm::Blake3
+                ),
+                root
+            );
+        }
+    }
+
+    #[test]
+    fn proofs_only_verify_against_their_root() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let first = TxnsBuilder::new()
+            .add_transfer(&user1, &key1, &user2, COIN, 0)
+            .add_transfer(&user1, &key1, &user2, 2 * COIN, 0)
+            .add_transfer(&user1, &key1, &user2, 3 * COIN, 0)
+            .build()
+            .unwrap();
+        let other = TxnsBuilder::new()
+            .add_transfer(&user1, &key1, &user2, COIN, 1)
+            .build()
+            .unwrap();
+        for txn in first.iter() {
+            let proof = first.generate_proof(&txn.id).unwrap();
+            assert!(proof.verify(&txn.id, &first.merkle_root));
+            assert!(!proof.verify(&txn.id, &other.merkle_root));
+        }
+        assert!(first.generate_proof(&Hash::default()).is_none());
+    }
+
+    #[test]
+    fn oversized_transactions_are_kept_out() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let crowd = vec![user1.address; MAX_TXN_SIZE / HASH_SIZE];
+        let crowded =
+            Txn::new_multisig(user2.address, &user1, COIN, 1, crowd);
+        let crowded_size = crowded.payload_size_bytes();
+        assert!(crowded_size > MAX_TXN_SIZE);
+        assert_eq!(crowded_size, crowded.to_encoded().len());
+        let mut roomy = Txns::new();
+        assert!(matches!(
+            roomy.add(crowded),
+            Err(TransactionError::TooLarge { size }) if size == crowded_size
+        ));
+        assert!(roomy.is_empty());
+        let small = Txn::new(&user1, &user2, COIN);
+        assert!(small.payload_size_bytes() < MAX_TXN_SIZE);
+        assert!(roomy.add(small).is_ok());
+    }
+
+    #[test]
+    fn repeated_transactions_can_be_dropped() {
+        let (user1, key1) = keyed_user("user1");
+        let (user2, key2) = keyed_user("user2");
+        let repeated = Txn::new_signed(&user1, &key1, &user2, 2 * COIN);
+        let mut doubled = Txns::new();
+        doubled.add(repeated.clone()).unwrap();
+        doubled
+            .add(Txn::new_signed(&user2, &key2, &user1, 3 * COIN))
+            .unwrap();
+        doubled.add(repeated.clone()).unwrap();
+        doubled.calc_merkle_root();
+        assert_eq!(doubled.first_duplicate(), Some(repeated.id));
+        assert_eq!(
+            Block::new(Hash::default(), doubled.clone(), 0).validate(),
+            Err(ValidationError::DuplicateTransaction {
+                id: repeated.id,
+                block_index: 0
+            })
+        );
+        assert_eq!(doubled.dedup(), 1);
+        assert_eq!(doubled.len(), 2);
+        assert_eq!(doubled.iter().next().unwrap().id, repeated.id);
+        assert_eq!(doubled.merkle_root, doubled.compute_merkle_root());
+        assert_eq!(doubled.dedup(), 0);
+        assert!(Block::new(Hash::default(), doubled, 0)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn expired_transactions_are_removed() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let now = now_millis() as u64;
+        let mut expiring = Txns::new();
+        expiring
+            .add(Txn::new(&user1, &user2, COIN).with_expiry(now - 1))
+            .unwrap();
+        expiring
+            .add(Txn::new(&user1, &user2, COIN).with_expiry(now + 60_000))
+            .unwrap();
+        expiring.calc_merkle_root();
+        assert_eq!(expiring.remove_expired(now), 1);
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring.merkle_root, expiring.compute_merkle_root());
+    }
+
+    #[test]
+    fn sets_can_be_sized_up_front() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut presized = Txns::with_capacity(8);
+        assert!(presized.is_empty() && presized.capacity() >= 8);
+        assert!(presized.reserve(100).capacity() >= 100);
+        presized.add(Txn::new(&user1, &user2, COIN)).unwrap();
+        assert_eq!(presized.len(), 1);
+    }
+
+    #[test]
+    fn sorting_keeps_the_coinbase_first() {
+        let (user1, _) = keyed_user("user1");
+        let (user2, _) = keyed_user("user2");
+        let mut by_fee = TxnsBuilder::new()
+            .add_coinbase(&user1, GENESIS_REWARD, 0)
+            .build()
+            .unwrap();
+        let oldest = Txn::new(&user1, &user2, COIN).with_fee(1);
+        by_fee.add(oldest.clone()).unwrap();
+        by_fee
+            .add(Txn::new(&user1, &user2, 2 * COIN).with_fee(9))
+            .unwrap();
+        by_fee
+            .add(Txn::new(&user1, &user2, 3 * COIN).with_fee(5))
+            .unwrap();
+        let unsorted_root = by_fee.merkle_root;
+        let fees: Vec<u64> = by_fee
+            .sort_by_fee_descending()
+            .iter()
+            .map(|txn| txn.fee)
+            .collect();
+        assert_eq!(fees, vec![0, 9, 5, 1]);
+        assert!(by_fee.iter().next().unwrap().is_coinbase());
+        assert_ne!(by_fee.merkle_root, unsorted_root);
+        assert_eq!(by_fee.merkle_root, by_fee.compute_merkle_root());
+        assert!(by_fee.verify().is_ok());
+
+        let by_age = by_fee.clone().sort_by_timestamp_ascending().clone();
+        assert!(by_age.iter().next().unwrap().is_coinbase());
+        assert_eq!(
+            by_age.iter().nth(1).unwrap().timestamp,
+            oldest.timestamp
+        );
+        assert!(by_age
+            .iter()
+            .skip(1)
+            .zip(by_age.iter().skip(2))
+            .all(|(a, b)| a.timestamp <= b.timestamp));
+    }
+}
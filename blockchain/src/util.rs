@@ -0,0 +1,34 @@
+use crate::hex::HexError;
+
+// Lowercase hex, two digits per byte
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Decode hex in either case
+pub fn hex_to_bytes(s: &str) -> Result<Vec<u8>, HexError> {
+    let chars: Vec<char> = s.chars().collect();
+    if !chars.len().is_multiple_of(2) {
+        return Err(HexError::OddLength);
+    }
+    let digit = |c: char| c.to_digit(16).ok_or(HexError::InvalidDigit(c));
+
+    chars
+        .chunks(2)
+        .map(|pair| Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips_in_either_case() {
+        assert_eq!(bytes_to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(hex_to_bytes("00AB10"), Ok(vec![0x00, 0xab, 0x10]));
+        assert_eq!(hex_to_bytes("0aFf"), Ok(vec![0x0a, 0xff]));
+        assert_eq!(hex_to_bytes("0g"), Err(HexError::InvalidDigit('g')));
+        assert_eq!(hex_to_bytes("abc"), Err(HexError::OddLength));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{Address, Hash};
+use std::fmt;
+
+// Why something failed to validate. More variants may be added, so
+// matches outside this crate need a wildcard arm.
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    InvalidSignature,
+    UnknownSender(Address),
+    InsufficientSignatures {
+        required: u8,
+        valid: u8,
+    },
+    MultipleInvalidSignatures(Vec<(Hash, ValidationError)>), // By txn id
+    HashMismatch,
+    MerkleRootMismatch,
+    BrokenChain {
+        expected: Hash,
+        got: Hash,
+    },
+    UnexpectedIndex {
+        expected: u32,
+        got: u32,
+    },
+    GenesisMismatch(u32), // Index disagrees with a zero/non-zero prev hash
+    InvalidTimestamp(u128),
+    InsufficientWork {
+        index: u32,
+        difficulty: u8,
+    },
+    InvalidRetargetIndex {
+        last_retarget_index: u32,
+        tip: u32, // The block being retargeted after
+    },
+    EmptyTransactionSet,
+    MisplacedCoinbase(usize), // Position of the offending transaction
+    SelfTransfer(Address),    // Sends to its own sender
+    ExpiredTransaction(Hash), // Mined after its expiry, by txn id
+    MetadataTooLarge(usize),  // Length of the offending metadata
+    DuplicateTransaction {
+        id: Hash,
+        block_index: u32, // Where it was first included
+    },
+    InsufficientFunds {
+        sender: Address,
+        available: u64,
+        requested: u64,
+    },
+    SupplyCapExceeded {
+        block_index: u32,
+        supply: u64, // What the supply would have been
+    },
+    UnsignedTransaction,
+    StaleTransactionId(Hash), // Changed since it was hashed, by its old id
+    BlockTooLarge {
+        block_index: u32,
+        size: usize, // Serialized bytes
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::InvalidSignature => {
+                write!(f, "invalid transaction signature")
+            }
+            ValidationError::UnknownSender(sender) => {
+                write!(f, "no public key for sender {}", sender)
+            }
+            ValidationError::InsufficientSignatures {
+                required,
+                valid,
+            } => {
+                write!(
+                    f,
+                    "{} of {} required signatures are valid",
+                    valid, required
+                )
+            }
+            ValidationError::MultipleInvalidSignatures(failures) => {
+                write!(
+                    f,
+                    "{} transactions failed signature checks",
+                    failures.len()
+                )
+            }
+            ValidationError::HashMismatch => {
+                write!(f, "stored hash does not match contents")
+            }
+            ValidationError::MerkleRootMismatch => {
+                write!(f, "merkle root does not match transactions")
+            }
+            ValidationError::BrokenChain { expected, got } => write!(
+                f,
+                "broken chain: expected prev hash {}, got {}",
+                expected, got
+            ),
+            ValidationError::UnexpectedIndex { expected, got } => write!(
+                f,
+                "unexpected block index: expected {}, got {}",
+                expected, got
+            ),
+            ValidationError::GenesisMismatch(index) => write!(
+                f,
+                "block {} has a prev hash inconsistent with its index",
+                index
+            ),
+            ValidationError::InvalidTimestamp(timestamp) => {
+                write!(f, "invalid timestamp {}", timestamp)
+            }
+            ValidationError::InsufficientWork { index, difficulty } => {
+                write!(
+                    f,
+                    "block {} does not meet difficulty {}",
+                    index, difficulty
+                )
+            }
+            ValidationError::InvalidRetargetIndex {
+                last_retarget_index,
+                tip,
+            } => write!(
+                f,
+                "difficulty last retargeted at block {}, after block {}",
+                last_retarget_index, tip
+            ),
+            ValidationError::EmptyTransactionSet => {
+                write!(f, "transaction set is empty")
+            }
+            ValidationError::MisplacedCoinbase(i) => write!(
+                f,
+                "coinbase reward at position {}, must be first",
+                i
+            ),
+            ValidationError::SelfTransfer(address) => {
+                write!(f, "{} sends to itself", address)
+            }
+            ValidationError::ExpiredTransaction(id) => {
+                write!(f, "transaction {} was mined after it expired", id)
+            }
+            ValidationError::MetadataTooLarge(len) => write!(
+                f,
+                "transaction metadata is {} bytes, at most {} allowed",
+                len,
+                crate::MAX_METADATA_SIZE
+            ),
+            ValidationError::DuplicateTransaction { id, block_index } => {
+                write!(
+                    f,
+                    "transaction {} is already in block {}",
+                    id, block_index
+                )
+            }
+            ValidationError::InsufficientFunds {
+                sender,
+                available,
+                requested,
+            } => write!(
+                f,
+                "sender {} has {} but tried to spend {}",
+                sender, available, requested
+            ),
+            ValidationError::SupplyCapExceeded {
+                block_index,
+                supply,
+            } => write!(
+                f,
+                "block {} would bring the supply to {}, at most {} allowed",
+                block_index,
+                supply,
+                crate::MAX_SUPPLY
+            ),
+            ValidationError::BlockTooLarge { block_index, size } => write!(
+                f,
+                "block {} is {} bytes, at most {} allowed",
+                block_index,
+                size,
+                crate::MAX_BLOCK_SIZE
+            ),
+            ValidationError::UnsignedTransaction => {
+                write!(f, "block contains an unsigned transaction")
+            }
+            ValidationError::StaleTransactionId(id) => write!(
+                f,
+                "transaction {} has changed since its id was computed",
+                id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
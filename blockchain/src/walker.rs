@@ -0,0 +1,45 @@
+use crate::{Address, Block, Txn};
+use std::slice;
+
+// Every transaction on a chain with the block that holds it, block by
+// block and in order within each block
+pub(crate) struct ChainWalker<'a> {
+    blocks: slice::Iter<'a, Block>,
+    current: Option<(&'a Block, slice::Iter<'a, Txn>)>,
+}
+
+impl<'a> ChainWalker<'a> {
+    pub fn new(blocks: &'a [Block]) -> Self {
+        Self {
+            blocks: blocks.iter(),
+            current: None,
+        }
+    }
+
+    // Only the transactions sent or received by `addr`
+    pub fn filter_by_address(
+        self,
+        addr: &Address,
+    ) -> impl Iterator<Item = (&'a Block, &'a Txn)> {
+        let addr = *addr;
+        self.filter(move |(_, txn)| {
+            txn.sender == addr || txn.recipient == addr
+        })
+    }
+}
+
+impl<'a> Iterator for ChainWalker<'a> {
+    type Item = (&'a Block, &'a Txn);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((block, txns)) = &mut self.current {
+                if let Some(txn) = txns.next() {
+                    return Some((*block, txn));
+                }
+            }
+            let block = self.blocks.next()?;
+            self.current = Some((block, block.txns.iter()));
+        }
+    }
+}